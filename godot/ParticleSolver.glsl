@@ -0,0 +1,136 @@
+#[compute]
+#version 450
+
+// Jacobi port of the entity particle solver, see rust/src/entity/gpu.rs
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+struct Neighbor {
+    uint target_index;
+    float rest_distance;
+    float compliance;
+    float padding;
+};
+
+// xyz is the position, w is the inverse mass
+layout(set = 0, binding = 0, std430) restrict readonly buffer PositionsIn {
+    vec4 positions_in[];
+};
+layout(set = 0, binding = 1, std430) restrict writeonly buffer PositionsOut {
+    vec4 positions_out[];
+};
+// w is the fraction of velocity kept each step
+layout(set = 0, binding = 2, std430) restrict readonly buffer OldPositions {
+    vec4 old_positions[];
+};
+layout(set = 0, binding = 3, std430) restrict readonly buffer Accelerations {
+    vec4 accelerations[];
+};
+// Neighbors of particle i are neighbors[offsets[i]] to neighbors[offsets[i + 1]]
+layout(set = 0, binding = 4, std430) restrict readonly buffer Offsets {
+    uint offsets[];
+};
+layout(set = 0, binding = 5, std430) restrict readonly buffer Neighbors {
+    Neighbor neighbors[];
+};
+// Positions after the prediction pass, for the velocity a particle hits the floor with
+layout(set = 0, binding = 6, std430) restrict buffer PredictedPositions {
+    vec4 predicted_positions[];
+};
+// Particles that can collide with particle i are colliders[collider_offsets[i]] to
+// colliders[collider_offsets[i + 1]]
+layout(set = 0, binding = 7, std430) restrict readonly buffer ColliderOffsets {
+    uint collider_offsets[];
+};
+layout(set = 0, binding = 8, std430) restrict readonly buffer Colliders {
+    uint colliders[];
+};
+
+layout(push_constant, std430) uniform Params {
+    float delta;
+    uint particle_count;
+    uint mode;
+    float relaxation;
+    float restitution;
+    float minimum_distance;
+    uint use_floor;
+    float padding;
+} params;
+
+const uint MODE_PREDICT = 0;
+const uint MODE_CONSTRAIN = 1;
+
+void main() {
+    uint i = gl_GlobalInvocationID.x;
+    if (i >= params.particle_count) {
+        return;
+    }
+
+    vec4 particle = positions_in[i];
+    vec3 position = particle.xyz;
+    float inverse_mass = particle.w;
+
+    // Pinned particles never move
+    if (inverse_mass <= 0.0) {
+        positions_out[i] = particle;
+        return;
+    }
+
+    if (params.mode == MODE_PREDICT) {
+        vec4 old_position = old_positions[i];
+        vec3 velocity = (position - old_position.xyz) * old_position.w;
+        position += velocity + accelerations[i].xyz * params.delta * params.delta;
+        predicted_positions[i] = vec4(position, inverse_mass);
+        positions_out[i] = vec4(position, inverse_mass);
+        return;
+    }
+
+    // Sum the corrections from every distance constraint touching this particle
+    vec3 correction = vec3(0.0);
+    uint start = offsets[i];
+    uint end = offsets[i + 1];
+    for (uint n = start; n < end; n++) {
+        Neighbor neighbor = neighbors[n];
+        vec4 other = positions_in[neighbor.target_index];
+        vec3 offset = position - other.xyz;
+        float current_distance = length(offset);
+        if (current_distance <= 1e-6) {
+            continue;
+        }
+        float alpha = neighbor.compliance / (params.delta * params.delta);
+        float denominator = inverse_mass + other.w + alpha;
+        if (denominator <= 1e-6) {
+            continue;
+        }
+        float constraint = current_distance - neighbor.rest_distance;
+        correction += (offset / current_distance) * (-constraint / denominator) * inverse_mass;
+    }
+    uint count = end - start;
+    if (count > 0) {
+        correction *= params.relaxation / float(count);
+    }
+
+    // Self-collision, each particle takes its share of every overlapping pair
+    for (uint n = collider_offsets[i]; n < collider_offsets[i + 1]; n++) {
+        vec4 other = positions_in[colliders[n]];
+        vec3 direction = other.xyz - position;
+        float current_distance = length(direction);
+        float total_inverse_mass = inverse_mass + other.w;
+        if (current_distance > 0.0 && current_distance < params.minimum_distance) {
+            correction -= (direction / current_distance)
+                * ((params.minimum_distance - current_distance) / total_inverse_mass)
+                * inverse_mass;
+        }
+    }
+    position += correction;
+
+    // Floor collision, bouncing back with the velocity the particle hit it with
+    if (params.use_floor != 0 && position.y < 0.0) {
+        float approach_velocity =
+            (predicted_positions[i].y - old_positions[i].y) / params.delta;
+        float bounce_velocity =
+            approach_velocity < 0.0 ? -approach_velocity * params.restitution : 0.0;
+        position.y = bounce_velocity * params.delta;
+    }
+
+    positions_out[i] = vec4(position, inverse_mass);
+}
@@ -0,0 +1,98 @@
+use super::{
+    materials::PropertiesTable, neighbor_lists, volume::centroid, Entity, ParticleMaterial,
+    PARTICLE_DISTANCE,
+};
+use godot::prelude::*;
+
+// Area of wing surface each particle stands for
+const WING_AREA: f32 = PARTICLE_DISTANCE * PARTICLE_DISTANCE;
+// Flat plate coefficient for air pushing against the face of the wing
+const PRESSURE_COEFFICIENT: f32 = 1.0;
+// Much weaker friction of air sliding along the wing
+const FRICTION_COEFFICIENT: f32 = 0.05;
+const NORMAL_ITERATIONS: usize = 8;
+
+impl Entity {
+    // Acceleration of each particle from air acting on the wings, zero for everything else.
+    // Lift comes from the part of the pressure force across the direction of travel.
+    pub fn wing_accelerations(
+        &self,
+        properties: &PropertiesTable,
+        substep_delta: f32,
+    ) -> Vec<Vector3> {
+        let mut accelerations = vec![Vector3::ZERO; self.particles.len()];
+        let is_wing = |index: usize| self.particles[index].material == ParticleMaterial::Wing;
+
+        let neighbors = neighbor_lists(&self.particles, |i, connection| {
+            connection.active && is_wing(i) && is_wing(connection.target_index)
+        });
+
+        for (i, particle) in self.particles.iter().enumerate() {
+            if !is_wing(i) || neighbors[i].len() < 2 {
+                continue;
+            }
+            let mass = properties.get(&particle.material).mass;
+            if mass <= 0.0 {
+                continue;
+            }
+
+            let mut positions = vec![particle.position];
+            positions.extend(neighbors[i].iter().map(|&n| self.particles[n].position));
+            let Some(normal) = surface_normal(&positions) else {
+                continue;
+            };
+
+            // The normal's sign doesn't matter as it appears twice in the pressure force
+            let velocity = (particle.position - particle.old_position) / substep_delta;
+            let speed = velocity.length();
+            let normal_speed = velocity.dot(normal);
+            let tangent_velocity = velocity - normal * normal_speed;
+            let dynamic_pressure = 0.5 * self.air_density * WING_AREA * speed;
+            let force = -normal * (dynamic_pressure * PRESSURE_COEFFICIENT * normal_speed)
+                - tangent_velocity * (dynamic_pressure * FRICTION_COEFFICIENT);
+            accelerations[i] = force / mass;
+        }
+        accelerations
+    }
+}
+
+// Direction the points spread least along, the normal of the patch of wing they lie on
+fn surface_normal(positions: &[Vector3]) -> Option<Vector3> {
+    let center = centroid(positions);
+    // Rows of the covariance matrix, symmetric so they are also the columns
+    let mut covariance = [Vector3::ZERO; 3];
+    for &position in positions {
+        let offset = position - center;
+        covariance[0] += offset * offset.x;
+        covariance[1] += offset * offset.y;
+        covariance[2] += offset * offset.z;
+    }
+
+    // Power iteration on trace - covariance, whose largest eigenvector is the covariance's smallest
+    let trace = covariance[0].x + covariance[1].y + covariance[2].z;
+    if trace <= f32::EPSILON {
+        return None;
+    }
+    let shifted = [
+        Vector3::new(trace, 0.0, 0.0) - covariance[0],
+        Vector3::new(0.0, trace, 0.0) - covariance[1],
+        Vector3::new(0.0, 0.0, trace) - covariance[2],
+    ];
+    // Start from the longest row, which leans towards the dominant eigenvector
+    let mut normal = shifted
+        .into_iter()
+        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))?;
+    for _ in 0..NORMAL_ITERATIONS {
+        let next = Vector3::new(
+            shifted[0].dot(normal),
+            shifted[1].dot(normal),
+            shifted[2].dot(normal),
+        );
+        let length = next.length();
+        if length <= f32::EPSILON {
+            return None;
+        }
+        normal = next / length;
+    }
+    Some(normal)
+}
@@ -0,0 +1,31 @@
+use super::{nearest_particle, Entity};
+use godot::prelude::*;
+
+impl Entity {
+    // Anchor every particle whose position passes the test, stopping it where it is
+    pub fn anchor_where(&mut self, test: impl Fn(Vector3) -> bool) -> i64 {
+        let mut anchored = 0;
+        for particle in &mut self.particles {
+            if test(particle.position) {
+                particle.anchored = true;
+                particle.old_position = particle.position;
+                anchored += 1;
+            }
+        }
+        godot_print!("Anchored {} particles", anchored);
+        anchored
+    }
+
+    pub fn anchor_nearest_particle(&mut self, point: Vector3, radius: f32) -> bool {
+        let Some(index) = nearest_particle(&self.particles, point, |_| true) else {
+            return false;
+        };
+        let particle = &mut self.particles[index];
+        if particle.position.distance_to(point) > radius {
+            return false;
+        }
+        particle.anchored = true;
+        particle.old_position = particle.position;
+        true
+    }
+}
@@ -0,0 +1,125 @@
+use super::{neighbor_lists, volume::centroid, Particle, ParticleMaterial};
+use godot::prelude::*;
+use std::collections::VecDeque;
+
+// Iterations of the rotation extraction, warm started so a few are plenty
+const ROTATION_ITERATIONS: usize = 8;
+
+// A connected region of bone, matched back onto its rest shape so it moves as one rigid part
+pub struct BoneGroup {
+    members: Vec<usize>,
+    // Rest positions of the members relative to the rest centroid
+    rest_offsets: Vec<Vector3>,
+    // Bone to bone connections holding the group together as (particle index, connection index)
+    links: Vec<(usize, usize)>,
+    // Best fit rotation from the last solve, carried over to warm start the next
+    rotation: Quaternion,
+}
+
+impl BoneGroup {
+    // A cut through the bone splits it, so the groups need rebuilding
+    pub fn is_intact(&self, particles: &[Particle]) -> bool {
+        self.links
+            .iter()
+            .all(|&(particle, connection)| particles[particle].connections[connection].active)
+    }
+}
+
+// Flood fill over active connections between bone particles
+pub fn build_bone_groups(particles: &[Particle]) -> Vec<BoneGroup> {
+    let is_bone = |index: usize| particles[index].material == ParticleMaterial::Bone;
+
+    let neighbors = neighbor_lists(particles, |i, connection| {
+        connection.active && is_bone(i) && is_bone(connection.target_index)
+    });
+
+    let mut grouped = vec![false; particles.len()];
+    let mut groups = Vec::new();
+    for start in 0..particles.len() {
+        if grouped[start] || !is_bone(start) {
+            continue;
+        }
+        grouped[start] = true;
+        let mut members = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(index) = queue.pop_front() {
+            members.push(index);
+            for &next in &neighbors[index] {
+                if !grouped[next] {
+                    grouped[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        if members.len() < 2 {
+            continue;
+        }
+
+        let positions: Vec<Vector3> = members.iter().map(|&i| particles[i].position).collect();
+        let rest_centroid = centroid(&positions);
+        let mut links = Vec::new();
+        for &i in &members {
+            for (c, connection) in particles[i].connections.iter().enumerate() {
+                if connection.active && is_bone(connection.target_index) {
+                    links.push((i, c));
+                }
+            }
+        }
+        groups.push(BoneGroup {
+            rest_offsets: positions
+                .iter()
+                .map(|&position| position - rest_centroid)
+                .collect(),
+            members,
+            links,
+            rotation: Quaternion::new(0.0, 0.0, 0.0, 1.0),
+        });
+    }
+    groups
+}
+
+// Shape matching, moving every free member onto the best rigid fit of the rest shape
+pub fn solve_bone_group(group: &mut BoneGroup, positions: &mut [Vector3], inverse_masses: &[f32]) {
+    let group_positions: Vec<Vector3> = group.members.iter().map(|&i| positions[i]).collect();
+    let current_centroid = centroid(&group_positions);
+
+    // Columns of the covariance between the current and rest offsets
+    let mut covariance = [Vector3::ZERO; 3];
+    for (&position, &rest_offset) in group_positions.iter().zip(&group.rest_offsets) {
+        let offset = position - current_centroid;
+        covariance[0] += offset * rest_offset.x;
+        covariance[1] += offset * rest_offset.y;
+        covariance[2] += offset * rest_offset.z;
+    }
+    group.rotation = extract_rotation(covariance, group.rotation);
+
+    let basis = Basis::from_quat(group.rotation);
+    for (&i, &rest_offset) in group.members.iter().zip(&group.rest_offsets) {
+        if inverse_masses[i] > 0.0 {
+            positions[i] = current_centroid + basis * rest_offset;
+        }
+    }
+}
+
+// Rotational part of the covariance, from Müller et al. "A Robust Method to Extract the
+// Rotational Part of Deformations"
+fn extract_rotation(covariance: [Vector3; 3], mut rotation: Quaternion) -> Quaternion {
+    let axes = [Vector3::RIGHT, Vector3::UP, Vector3::BACK];
+    for _ in 0..ROTATION_ITERATIONS {
+        let basis = Basis::from_quat(rotation);
+        let columns = axes.map(|axis| basis * axis);
+        let torque = columns[0].cross(covariance[0])
+            + columns[1].cross(covariance[1])
+            + columns[2].cross(covariance[2]);
+        let alignment = columns[0].dot(covariance[0])
+            + columns[1].dot(covariance[1])
+            + columns[2].dot(covariance[2]);
+        let omega = torque / (alignment.abs() + 1e-9);
+        let angle = omega.length();
+        if angle < 1e-9 {
+            break;
+        }
+        rotation = (Quaternion::from_axis_angle(omega / angle, angle) * rotation).normalized();
+    }
+    rotation
+}
@@ -0,0 +1,78 @@
+use super::{materials::PropertiesTable, spatial::SpatialHash, Entity, PARTICLE_DISTANCE};
+use godot::prelude::*;
+
+impl Entity {
+    // Push this entity's particles apart from those of other entities.
+    // Each pair of entities is only resolved by the one with the lower instance id.
+    pub fn collide_with_entities(&mut self, properties: &PropertiesTable) {
+        let Some(mut tree) = self.base.get_tree() else {
+            return;
+        };
+        let own_id = self.base.instance_id().to_i64();
+        let mut others: Vec<Gd<Self>> = tree
+            .get_nodes_in_group("Entity".into())
+            .iter_shared()
+            .filter_map(|node| node.try_cast::<Self>())
+            .filter(|other| other.instance_id().to_i64() > own_id)
+            .collect();
+        if others.is_empty() {
+            return;
+        }
+
+        // Shared broadphase over the surface particles of every other entity
+        let mut owners = Vec::new();
+        let mut positions = Vec::new();
+        let mut inverse_masses = Vec::new();
+        for (other_index, other) in others.iter().enumerate() {
+            let other = other.bind();
+            let other_properties = PropertiesTable::from_resource(other.material_table.as_ref());
+            for (particle_index, particle) in other.particles.iter().enumerate() {
+                if particle.interior {
+                    continue;
+                }
+                owners.push((other_index, particle_index));
+                positions.push(particle.position);
+                inverse_masses.push(particle.inverse_mass(&other_properties));
+            }
+        }
+        let grid = SpatialHash::from_positions(PARTICLE_DISTANCE, positions.iter().copied());
+
+        // Rigid contacts split by inverse mass, batched so neither side moves mid-pass
+        let minimum_distance = PARTICLE_DISTANCE;
+        let mut own_corrections = Vec::new();
+        let mut other_corrections = Vec::new();
+        for (particle_index, particle) in self.particles.iter().enumerate() {
+            if particle.interior {
+                continue;
+            }
+            let w1 = particle.inverse_mass(properties);
+            for other_index in grid.neighbors(particle.position) {
+                let w2 = inverse_masses[other_index];
+                if w1 + w2 <= 0.0 {
+                    continue;
+                }
+                let dir = positions[other_index] - particle.position;
+                let current_distance = dir.length();
+                if current_distance > 0.0 && current_distance < minimum_distance {
+                    let correction = (dir / current_distance)
+                        * ((minimum_distance - current_distance) / (w1 + w2));
+                    own_corrections.push((particle_index, -correction * w1));
+                    other_corrections.push((owners[other_index], correction * w2));
+                }
+            }
+        }
+
+        // Apply corrections
+        for (index, correction) in own_corrections {
+            self.particles[index].position += correction;
+        }
+        for (other_index, other) in others.iter_mut().enumerate() {
+            let mut other = other.bind_mut();
+            for &((owner, index), correction) in &other_corrections {
+                if owner == other_index {
+                    other.particles[index].position += correction;
+                }
+            }
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use super::Entity;
-use anyhow::{Context, Result};
+use super::{damage::CUT_DAMAGE, materials::PropertiesTable, Entity};
+use anyhow::Result;
 use godot::{
     engine::{mesh::PrimitiveType, ImmediateMesh, Material},
     prelude::*,
@@ -8,15 +8,7 @@ use std::f32::consts::PI;
 
 impl Entity {
     fn get_camera_transform(&self) -> Result<Transform3D> {
-        let transform = self
-            .base
-            .get_viewport()
-            .context("Failed to get node's viewport")?
-            .get_camera_3d()
-            .context("Viewport does not have a camera")?
-            .get_global_transform();
-
-        Ok(transform)
+        Ok(self.get_camera()?.get_global_transform())
     }
 
     pub fn get_cut_plane(&self) -> Result<(Vector3, Vector3, Vector2)> {
@@ -59,10 +51,24 @@ impl Entity {
         }
 
         // Make cuts
+        if cut_connections.is_empty() {
+            return Ok(());
+        }
+        let mut severed = Vec::new();
         for (i1, i2) in cut_connections {
-            self.particles[i1].connections[i2].active = false;
+            let connection = &mut self.particles[i1].connections[i2];
+            connection.active = false;
+            let target_index = connection.target_index;
+            self.expose(i1, target_index);
+            self.islands.disconnect(i1, target_index);
+            self.particles[i1].damage += CUT_DAMAGE;
+            self.particles[target_index].damage += CUT_DAMAGE;
+            severed.push((i1, target_index));
         }
-        Ok(())
+        self.mark_topology_dirty();
+        let properties = PropertiesTable::from_resource(self.material_table.as_ref());
+        self.wound(&severed, &properties);
+        self.split_islands()
     }
 }
 
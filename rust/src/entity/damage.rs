@@ -0,0 +1,123 @@
+use super::{materials::PropertiesTable, nearest_particle, Entity};
+use godot::prelude::*;
+
+// Strain beyond this fraction of the break strain starts hurting the particles at either end
+const STRAIN_DAMAGE_FRACTION: f32 = 0.5;
+// Damage per second from a connection stretched all the way to its break strain
+const STRAIN_DAMAGE_RATE: f32 = 1.0;
+// Change in velocity within one step that a particle shrugs off
+const IMPACT_THRESHOLD: f32 = 5.0;
+// Damage per unit of velocity change beyond the threshold
+const IMPACT_DAMAGE: f32 = 0.1;
+// Damage to both particles either side of a cut
+pub const CUT_DAMAGE: f32 = 0.5;
+
+impl Entity {
+    pub fn particle_velocities(&self, substep_delta: f32) -> Vec<Vector3> {
+        self.particles
+            .iter()
+            .map(|particle| (particle.position - particle.old_position) / substep_delta)
+            .collect()
+    }
+
+    // Hurt particles that were stretched or slammed to a stop during the last step
+    pub fn accumulate_damage(
+        &mut self,
+        properties: &PropertiesTable,
+        velocities_before: &[Vector3],
+        substep_delta: f32,
+    ) {
+        let mut damage = vec![0.0; self.particles.len()];
+
+        for (i, particle) in self.particles.iter().enumerate() {
+            for connection in &particle.connections {
+                if !connection.active {
+                    continue;
+                }
+                let j = connection.target_index;
+                let other = &self.particles[j];
+                let break_strain = f32::min(
+                    properties.get(&particle.material).break_strain,
+                    properties.get(&other.material).break_strain,
+                );
+                let current_distance = particle.position.distance_to(other.position);
+                let strain = (current_distance - connection.distance) / connection.distance;
+                let excess = strain / break_strain - STRAIN_DAMAGE_FRACTION;
+                if excess > 0.0 {
+                    let strain_damage = excess * STRAIN_DAMAGE_RATE * self.time_step;
+                    damage[i] += strain_damage;
+                    damage[j] += strain_damage;
+                }
+            }
+        }
+
+        let velocities_after = self.particle_velocities(substep_delta);
+        for (i, (before, after)) in velocities_before.iter().zip(velocities_after).enumerate() {
+            let impact = before.distance_to(after) - IMPACT_THRESHOLD;
+            if impact > 0.0 {
+                damage[i] += impact * IMPACT_DAMAGE;
+            }
+        }
+
+        for (particle, damage) in self.particles.iter_mut().zip(damage) {
+            particle.damage += damage;
+        }
+    }
+
+    // Kill particles past their toughness, cutting them loose so they drop away when islands split
+    pub fn kill_damaged_particles(&mut self, properties: &PropertiesTable) {
+        let mut killed = 0;
+        for particle in &mut self.particles {
+            if particle.alive && particle.damage >= properties.get(&particle.material).toughness {
+                particle.alive = false;
+                killed += 1;
+            }
+        }
+        if killed == 0 {
+            return;
+        }
+
+        let mut cut_connections = Vec::new();
+        for (i, particle) in self.particles.iter().enumerate() {
+            for (c, connection) in particle.connections.iter().enumerate() {
+                let target_alive = self.particles[connection.target_index].alive;
+                if connection.active && !(particle.alive && target_alive) {
+                    cut_connections.push((i, c));
+                }
+            }
+        }
+        let mut severed = Vec::new();
+        for (i, c) in cut_connections {
+            let connection = &mut self.particles[i].connections[c];
+            connection.active = false;
+            let target_index = connection.target_index;
+            self.expose(i, target_index);
+            self.islands.disconnect(i, target_index);
+            severed.push((i, target_index));
+        }
+        self.mark_topology_dirty();
+        self.wound(&severed, properties);
+
+        // Keep the body centered on a living particle
+        if !self.particles[self.central_particle].alive {
+            let central_position = self.particles[self.central_particle].position;
+            if let Some(index) =
+                nearest_particle(&self.particles, central_position, |particle| particle.alive)
+            {
+                self.central_particle = index;
+            }
+        }
+
+        godot_print!("Killed {} particles", killed);
+    }
+
+    // Called once when the heart stops, from damage or being severed from the body
+    pub fn die(&mut self) {
+        if !self.alive {
+            return;
+        }
+        self.alive = false;
+        godot_print!("Entity died");
+        self.emit_deferred("died", &[]);
+    }
+}
@@ -0,0 +1,118 @@
+use super::{materials::PropertiesTable, Entity, PARTICLE_DISTANCE};
+use anyhow::{Context, Result};
+use godot::{
+    engine::{global::MouseButton, Camera3D, InputEventMouseButton},
+    prelude::*,
+};
+
+// How far from the mouse ray a particle can be and still get picked
+const PICK_RADIUS: f32 = PARTICLE_DISTANCE;
+// Softness of the spring pulling the dragged particle to the mouse
+const DRAG_COMPLIANCE: f32 = 0.001;
+
+// A particle held by the mouse, kept at the same depth along the camera ray it was picked at
+pub struct Drag {
+    particle: usize,
+    depth: f32,
+    target: Vector3,
+}
+
+impl Entity {
+    pub fn get_camera(&self) -> Result<Gd<Camera3D>> {
+        self.base
+            .get_viewport()
+            .context("Failed to get node's viewport")?
+            .get_camera_3d()
+            .context("Viewport does not have a camera")
+    }
+
+    // Start dragging on left click, let go on release
+    pub fn handle_mouse_button(&mut self, event: &Gd<InputEventMouseButton>) -> Result<()> {
+        if !self.allow_dragging || event.get_button_index() != MouseButton::MOUSE_BUTTON_LEFT {
+            return Ok(());
+        }
+        if !event.is_pressed() {
+            self.drag = None;
+            return Ok(());
+        }
+
+        let camera = self.get_camera()?;
+        let mouse_position = event.get_position();
+        let origin = camera.project_ray_origin(mouse_position);
+        let direction = camera.project_ray_normal(mouse_position);
+
+        // Pick the surface particle closest to the camera that the ray passes near
+        let mut picked: Option<(usize, f32)> = None;
+        for (index, particle) in self.particles.iter().enumerate() {
+            if particle.interior {
+                continue;
+            }
+            let depth = (particle.position - origin).dot(direction);
+            if depth <= 0.0 {
+                continue;
+            }
+            let distance_to_ray = particle.position.distance_to(origin + direction * depth);
+            if distance_to_ray > PICK_RADIUS {
+                continue;
+            }
+            let is_closer = match picked {
+                Some((_, picked_depth)) => depth < picked_depth,
+                None => true,
+            };
+            if is_closer {
+                picked = Some((index, depth));
+            }
+        }
+
+        if let Some((particle, depth)) = picked {
+            self.drag = Some(Drag {
+                particle,
+                depth,
+                target: self.particles[particle].position,
+            });
+            // Stop entities behind this one picking as well
+            self.base
+                .get_viewport()
+                .context("Failed to get node's viewport")?
+                .set_input_as_handled();
+        }
+        Ok(())
+    }
+
+    // Follow the mouse with the drag target
+    pub fn update_drag_target(&mut self) -> Result<()> {
+        let Some(depth) = self.drag.as_ref().map(|drag| drag.depth) else {
+            return Ok(());
+        };
+        let camera = self.get_camera()?;
+        let mouse_position = self
+            .base
+            .get_viewport()
+            .context("Failed to get node's viewport")?
+            .get_mouse_position();
+        let target = camera.project_ray_origin(mouse_position)
+            + camera.project_ray_normal(mouse_position) * depth;
+        if let Some(drag) = &mut self.drag {
+            drag.target = target;
+        }
+        Ok(())
+    }
+
+    // Zero length XPBD spring between the dragged particle and the target
+    pub fn apply_drag(&mut self, properties: &PropertiesTable, delta: f32) {
+        let Some(drag) = &self.drag else {
+            return;
+        };
+        let Some(particle) = self.particles.get_mut(drag.particle) else {
+            return;
+        };
+        let inverse_mass = particle.inverse_mass(properties);
+        if inverse_mass <= 0.0 {
+            return;
+        }
+        let alpha = DRAG_COMPLIANCE / (delta * delta);
+        let correction =
+            (drag.target - particle.position) * (inverse_mass / (inverse_mass + alpha));
+        particle.position += correction;
+    }
+}
@@ -0,0 +1,442 @@
+use super::{
+    materials::PropertiesTable, neighbor_links, physics::RESTITUTION, spatial::SpatialHash,
+    Particle, PARTICLE_DISTANCE,
+};
+use anyhow::{Context, Result};
+use godot::{
+    engine::{
+        rendering_device::UniformType, RdShaderFile, RdUniform, RenderingDevice, RenderingServer,
+        ResourceLoader,
+    },
+    prelude::*,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+const SOLVER_ITERATIONS: u32 = 4;
+// Jacobi iterations under-correct when averaging, over-relax to converge faster
+const RELAXATION: f32 = 1.5;
+
+const MODE_PREDICT: u32 = 0;
+const MODE_CONSTRAIN: u32 = 1;
+
+// Runs the particle solver as a compute shader on a local rendering device
+pub struct GpuSolver {
+    device: Gd<RenderingDevice>,
+    shader: Rid,
+    pipeline: Rid,
+    buffers: Option<GpuBuffers>,
+    // Connections as last uploaded, only rebuilt when the topology or material table changes
+    offsets: Vec<u32>,
+    neighbors: Vec<f32>,
+    topology_properties: Option<PropertiesTable>,
+    topology_dirty: bool,
+}
+
+// Storage buffers sized for a particular particle and connection count
+struct GpuBuffers {
+    particle_count: usize,
+    neighbor_count: usize,
+    collider_capacity: usize,
+    positions_a: Rid,
+    positions_b: Rid,
+    old_positions: Rid,
+    accelerations: Rid,
+    offsets: Rid,
+    neighbors: Rid,
+    predicted_positions: Rid,
+    collider_offsets: Rid,
+    colliders: Rid,
+    // Reads from a and writes to b, and the reverse
+    uniform_set_ab: Rid,
+    uniform_set_ba: Rid,
+}
+
+impl GpuSolver {
+    pub fn new() -> Result<Self> {
+        let mut device = RenderingServer::singleton()
+            .create_local_rendering_device()
+            .context("Renderer does not support local rendering devices")?;
+
+        let spirv = ResourceLoader::singleton()
+            .load("res://ParticleSolver.glsl".into())
+            .context("Failed to load particle solver shader")?
+            .try_cast::<RdShaderFile>()
+            .context("Failed to cast resource to RDShaderFile")?
+            .get_spirv()
+            .context("Particle solver shader has no SPIR-V")?;
+        let shader = device.shader_create_from_spirv(spirv);
+        if !shader.is_valid() {
+            device.free();
+            anyhow::bail!("Failed to compile particle solver shader");
+        }
+        let pipeline = device.compute_pipeline_create(shader);
+
+        Ok(Self {
+            device,
+            shader,
+            pipeline,
+            buffers: None,
+            offsets: Vec::new(),
+            neighbors: Vec::new(),
+            topology_properties: None,
+            topology_dirty: true,
+        })
+    }
+
+    // Connections were cut, torn or reloaded, so upload them again on the next step
+    pub fn mark_topology_dirty(&mut self) {
+        self.topology_dirty = true;
+    }
+
+    pub fn process_step(
+        &mut self,
+        particles: &[Particle],
+        properties: &PropertiesTable,
+        external_accelerations: &[Vector3],
+        use_floor: bool,
+        delta: f32,
+    ) -> Vec<(usize, Vector3)> {
+        if particles.is_empty() {
+            return Vec::new();
+        }
+
+        // Connections only change on cuts, tears, kills and loads, or when the material table is
+        // edited, so reuse the ones already on the device otherwise
+        let topology_changed = self.topology_dirty
+            || self.topology_properties.as_ref() != Some(properties)
+            || self.offsets.len() != particles.len() + 1;
+        if topology_changed {
+            (self.offsets, self.neighbors) = build_neighbors(particles, properties);
+            self.topology_properties = Some(*properties);
+            self.topology_dirty = false;
+        }
+
+        // Colliders follow the particles as they move, so they are rebuilt every substep
+        let (collider_offsets, colliders) = build_colliders(particles);
+        let neighbor_count = self.neighbors.len() / 4;
+        let needs_rebuild = self.buffers.as_ref().map_or(true, |buffers| {
+            buffers.particle_count != particles.len()
+                || buffers.neighbor_count != neighbor_count
+                || buffers.collider_capacity < colliders.len()
+        });
+        if needs_rebuild {
+            self.free_buffers();
+            // Leave room for the colliders to grow as the body moves
+            let collider_capacity = (colliders.len() * 2).max(1);
+            self.buffers = Some(self.create_buffers(particles.len(), collider_capacity));
+        } else if topology_changed {
+            if let Some(buffers) = &self.buffers {
+                update_buffer(&mut self.device, buffers.offsets, &u32_bytes(&self.offsets));
+                update_buffer(
+                    &mut self.device,
+                    buffers.neighbors,
+                    &f32_bytes(&self.neighbors),
+                );
+            }
+        }
+        let Some(buffers) = &self.buffers else {
+            return Vec::new();
+        };
+        update_buffer(
+            &mut self.device,
+            buffers.collider_offsets,
+            &u32_bytes(&collider_offsets),
+        );
+        if !colliders.is_empty() {
+            update_buffer(&mut self.device, buffers.colliders, &u32_bytes(&colliders));
+        }
+
+        // Upload the current state
+        let gravity = Vector3::new(0.0, -10.0, 0.0);
+        let mut positions = Vec::with_capacity(particles.len() * 4);
+        let mut old_positions = Vec::with_capacity(particles.len() * 4);
+        let mut accelerations = Vec::with_capacity(particles.len() * 4);
+        for (particle, &external) in particles.iter().zip(external_accelerations) {
+            let material = properties.get(&particle.material);
+            let acceleration = gravity + Vector3::new(0.0, material.buoyancy, 0.0) + external;
+            push_vec4(
+                &mut positions,
+                particle.position,
+                particle.inverse_mass(properties),
+            );
+            // The shader scales velocity by w
+            push_vec4(
+                &mut old_positions,
+                particle.old_position,
+                1.0 - material.damping,
+            );
+            push_vec4(&mut accelerations, acceleration, 0.0);
+        }
+        update_buffer(
+            &mut self.device,
+            buffers.positions_a,
+            &f32_bytes(&positions),
+        );
+        update_buffer(
+            &mut self.device,
+            buffers.old_positions,
+            &f32_bytes(&old_positions),
+        );
+        update_buffer(
+            &mut self.device,
+            buffers.accelerations,
+            &f32_bytes(&accelerations),
+        );
+
+        // Predict into b, then ping-pong between the buffers for each iteration
+        #[allow(clippy::cast_possible_truncation)]
+        let particle_count = particles.len() as u32;
+        let groups = particle_count.div_ceil(WORKGROUP_SIZE);
+        let list = self.device.compute_list_begin();
+        self.device
+            .compute_list_bind_compute_pipeline(list, self.pipeline);
+        let mut reads_from_a = true;
+        for pass in 0..=SOLVER_ITERATIONS {
+            let mode = if pass == 0 {
+                MODE_PREDICT
+            } else {
+                MODE_CONSTRAIN
+            };
+            let uniform_set = if reads_from_a {
+                buffers.uniform_set_ab
+            } else {
+                buffers.uniform_set_ba
+            };
+            let push_constant = push_constant_bytes(delta, particle_count, mode, use_floor);
+            #[allow(clippy::cast_possible_truncation)]
+            let push_constant_size = push_constant.len() as u32;
+            self.device
+                .compute_list_bind_uniform_set(list, uniform_set, 0);
+            self.device.compute_list_set_push_constant(
+                list,
+                PackedByteArray::from(push_constant.as_slice()),
+                push_constant_size,
+            );
+            self.device.compute_list_dispatch(list, groups, 1, 1);
+            self.device.compute_list_add_barrier(list);
+            reads_from_a = !reads_from_a;
+        }
+        self.device.compute_list_end();
+        self.device.submit();
+        self.device.sync();
+
+        // The last pass wrote into whichever buffer would be read next
+        let result_buffer = if reads_from_a {
+            buffers.positions_a
+        } else {
+            buffers.positions_b
+        };
+        let bytes = self.device.buffer_get_data(result_buffer).to_vec();
+
+        bytes
+            .chunks_exact(16)
+            .take(particles.len())
+            .enumerate()
+            .map(|(i, chunk)| {
+                let component = |offset: usize| {
+                    f32::from_le_bytes([
+                        chunk[offset],
+                        chunk[offset + 1],
+                        chunk[offset + 2],
+                        chunk[offset + 3],
+                    ])
+                };
+                (i, Vector3::new(component(0), component(4), component(8)))
+            })
+            .collect()
+    }
+
+    // Sized for the given particle count and the cached connections
+    fn create_buffers(&mut self, particle_count: usize, collider_capacity: usize) -> GpuBuffers {
+        let vec4_bytes = vec![0; particle_count.max(1) * 16];
+        let positions_a = create_storage_buffer(&mut self.device, &vec4_bytes);
+        let positions_b = create_storage_buffer(&mut self.device, &vec4_bytes);
+        let old_positions = create_storage_buffer(&mut self.device, &vec4_bytes);
+        let accelerations = create_storage_buffer(&mut self.device, &vec4_bytes);
+        let offsets_buffer = create_storage_buffer(&mut self.device, &u32_bytes(&self.offsets));
+        // Storage buffers can't be empty, keep at least one neighbor's worth of space
+        let mut neighbor_bytes = f32_bytes(&self.neighbors);
+        neighbor_bytes.resize(neighbor_bytes.len().max(16), 0);
+        let neighbors_buffer = create_storage_buffer(&mut self.device, &neighbor_bytes);
+        let predicted_positions = create_storage_buffer(&mut self.device, &vec4_bytes);
+        let collider_offsets =
+            create_storage_buffer(&mut self.device, &vec![0; (particle_count + 1) * 4]);
+        let colliders = create_storage_buffer(&mut self.device, &vec![0; collider_capacity * 4]);
+
+        let uniform_set_ab = self.create_uniform_set(&[
+            positions_a,
+            positions_b,
+            old_positions,
+            accelerations,
+            offsets_buffer,
+            neighbors_buffer,
+            predicted_positions,
+            collider_offsets,
+            colliders,
+        ]);
+        let uniform_set_ba = self.create_uniform_set(&[
+            positions_b,
+            positions_a,
+            old_positions,
+            accelerations,
+            offsets_buffer,
+            neighbors_buffer,
+            predicted_positions,
+            collider_offsets,
+            colliders,
+        ]);
+
+        GpuBuffers {
+            particle_count,
+            neighbor_count: self.neighbors.len() / 4,
+            collider_capacity,
+            positions_a,
+            positions_b,
+            old_positions,
+            accelerations,
+            offsets: offsets_buffer,
+            neighbors: neighbors_buffer,
+            predicted_positions,
+            collider_offsets,
+            colliders,
+            uniform_set_ab,
+            uniform_set_ba,
+        }
+    }
+
+    fn create_uniform_set(&mut self, buffers: &[Rid]) -> Rid {
+        let mut uniforms = Array::new();
+        for (binding, buffer) in buffers.iter().enumerate() {
+            let mut uniform = RdUniform::new();
+            uniform.set_uniform_type(UniformType::UNIFORM_TYPE_STORAGE_BUFFER);
+            #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+            uniform.set_binding(binding as i32);
+            uniform.add_id(*buffer);
+            uniforms.push(uniform);
+        }
+        self.device.uniform_set_create(uniforms, self.shader, 0)
+    }
+
+    fn free_buffers(&mut self) {
+        if let Some(buffers) = self.buffers.take() {
+            // Uniform sets depend on the buffers, so free them first
+            for rid in [
+                buffers.uniform_set_ab,
+                buffers.uniform_set_ba,
+                buffers.positions_a,
+                buffers.positions_b,
+                buffers.old_positions,
+                buffers.accelerations,
+                buffers.offsets,
+                buffers.neighbors,
+                buffers.predicted_positions,
+                buffers.collider_offsets,
+                buffers.colliders,
+            ] {
+                self.device.free_rid(rid);
+            }
+        }
+    }
+}
+
+impl Drop for GpuSolver {
+    fn drop(&mut self) {
+        self.free_buffers();
+        self.device.free_rid(self.pipeline);
+        self.device.free_rid(self.shader);
+        self.device.clone().free();
+    }
+}
+
+// Flatten the active connections into a symmetric adjacency list, as offsets and
+// (target index, rest distance, compliance, padding) entries
+fn build_neighbors(particles: &[Particle], properties: &PropertiesTable) -> (Vec<u32>, Vec<f32>) {
+    let links = neighbor_links(particles, |_, connection| connection.active);
+
+    let mut offsets = Vec::with_capacity(particles.len() + 1);
+    let mut neighbors = Vec::new();
+    offsets.push(0);
+    for (i, list) in links.into_iter().enumerate() {
+        for link in list {
+            let target_index = link.neighbor;
+            let rest_distance = particles[link.owner].connections[link.connection].distance;
+            let compliance = (properties.get(&particles[i].material).compliance
+                + properties.get(&particles[target_index].material).compliance)
+                * 0.5;
+            // The target index is stored bit for bit, the shader reads it as a uint
+            #[allow(clippy::cast_possible_truncation)]
+            neighbors.push(f32::from_bits(target_index as u32));
+            neighbors.push(rest_distance);
+            neighbors.push(compliance);
+            neighbors.push(0.0);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        offsets.push((neighbors.len() / 4) as u32);
+    }
+    (offsets, neighbors)
+}
+
+// Particles close enough to collide with each one, as offsets and indices. Pairs of interior
+// particles are left out, they are held apart by their connections alone.
+fn build_colliders(particles: &[Particle]) -> (Vec<u32>, Vec<u32>) {
+    let grid = SpatialHash::from_positions(
+        PARTICLE_DISTANCE,
+        particles.iter().map(|particle| particle.position),
+    );
+
+    let mut offsets = Vec::with_capacity(particles.len() + 1);
+    let mut colliders = Vec::new();
+    offsets.push(0);
+    for (i, particle) in particles.iter().enumerate() {
+        for j in grid.neighbors(particle.position) {
+            if i != j && !(particle.interior && particles[j].interior) {
+                #[allow(clippy::cast_possible_truncation)]
+                colliders.push(j as u32);
+            }
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        offsets.push(colliders.len() as u32);
+    }
+    (offsets, colliders)
+}
+
+fn push_vec4(data: &mut Vec<f32>, vector: Vector3, w: f32) {
+    data.extend_from_slice(&[vector.x, vector.y, vector.z, w]);
+}
+
+// Push constants must be a multiple of 16 bytes, so the block ends in padding
+fn push_constant_bytes(delta: f32, particle_count: u32, mode: u32, use_floor: bool) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(32);
+    bytes.extend_from_slice(&delta.to_le_bytes());
+    bytes.extend_from_slice(&particle_count.to_le_bytes());
+    bytes.extend_from_slice(&mode.to_le_bytes());
+    bytes.extend_from_slice(&RELAXATION.to_le_bytes());
+    bytes.extend_from_slice(&RESTITUTION.to_le_bytes());
+    bytes.extend_from_slice(&PARTICLE_DISTANCE.to_le_bytes());
+    bytes.extend_from_slice(&u32::from(use_floor).to_le_bytes());
+    bytes.resize(32, 0);
+    bytes
+}
+
+fn f32_bytes(data: &[f32]) -> Vec<u8> {
+    data.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn u32_bytes(data: &[u32]) -> Vec<u8> {
+    data.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn create_storage_buffer(device: &mut Gd<RenderingDevice>, bytes: &[u8]) -> Rid {
+    #[allow(clippy::cast_possible_truncation)]
+    let size = bytes.len() as u32;
+    device
+        .storage_buffer_create_ex(size)
+        .data(PackedByteArray::from(bytes))
+        .done()
+}
+
+fn update_buffer(device: &mut Gd<RenderingDevice>, buffer: Rid, bytes: &[u8]) {
+    #[allow(clippy::cast_possible_truncation)]
+    let size = bytes.len() as u32;
+    device.buffer_update(buffer, 0, size, PackedByteArray::from(bytes));
+}
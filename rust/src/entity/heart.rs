@@ -0,0 +1,83 @@
+use super::{neighbor_lists, volume::centroid, Entity, ParticleMaterial};
+use godot::prelude::*;
+use std::collections::VecDeque;
+
+// Pulses weaker than this fraction of the full strength are not worth propagating
+const MIN_PULSE_FRACTION: f32 = 0.01;
+
+impl Entity {
+    // Advance the heartbeat by one fixed step, pulsing when a beat comes round
+    pub fn beat_heart(&mut self, substep_delta: f32) {
+        let has_heart = self
+            .particles
+            .iter()
+            .any(|particle| particle.alive && particle.material == ParticleMaterial::Heart);
+        if !has_heart {
+            if self.heart_beating {
+                self.heart_beating = false;
+                godot_print!("Heart stopped");
+                self.emit_deferred("heart_stopped", &[]);
+                self.die();
+            }
+            return;
+        }
+        self.heart_beating = true;
+
+        self.heart_phase += self.time_step * self.heart_rate / 60.0;
+        if self.heart_phase < 1.0 {
+            return;
+        }
+        self.heart_phase = self.heart_phase.fract();
+        self.pulse(substep_delta);
+    }
+
+    // Push particles outwards from the heart, weakening with every connection the pulse crosses
+    fn pulse(&mut self, substep_delta: f32) {
+        let neighbors = neighbor_lists(&self.particles, |_, connection| connection.active);
+
+        let heart: Vec<usize> = (0..self.particles.len())
+            .filter(|&i| {
+                self.particles[i].alive && self.particles[i].material == ParticleMaterial::Heart
+            })
+            .collect();
+        let heart_positions: Vec<Vector3> =
+            heart.iter().map(|&i| self.particles[i].position).collect();
+        let heart_center = centroid(&heart_positions);
+
+        // Breadth first, so each particle is reached by its shortest path from the heart
+        let mut strengths: Vec<Option<f32>> = vec![None; self.particles.len()];
+        let mut queue = VecDeque::new();
+        for &i in &heart {
+            strengths[i] = Some(self.pulse_strength);
+            queue.push_back(i);
+        }
+        while let Some(index) = queue.pop_front() {
+            let Some(strength) = strengths[index] else {
+                continue;
+            };
+            let next_strength = strength * self.pulse_attenuation;
+            if next_strength < self.pulse_strength * MIN_PULSE_FRACTION {
+                continue;
+            }
+            for &next in &neighbors[index] {
+                if strengths[next].is_none() {
+                    strengths[next] = Some(next_strength);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        // Give each reached particle an outward velocity, by moving where it was last step
+        for (particle, strength) in self.particles.iter_mut().zip(strengths) {
+            let Some(strength) = strength else {
+                continue;
+            };
+            let offset = particle.position - heart_center;
+            let distance = offset.length();
+            if distance <= f32::EPSILON {
+                continue;
+            }
+            particle.old_position -= (offset / distance) * strength * substep_delta;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use super::IntVec3;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+const FACE_NEIGHBORS: [IntVec3; 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+const fn offset(cell: IntVec3, direction: IntVec3) -> IntVec3 {
+    (
+        cell.0 + direction.0,
+        cell.1 + direction.1,
+        cell.2 + direction.2,
+    )
+}
+
+// Which particles on the voxel grid lie deeper than the shell thickness below the outside air.
+// Air is flood filled from outside the bounds, so sealed cavities count as solid.
+pub fn find_interior(cells: &[IntVec3], shell_thickness: u32) -> Vec<bool> {
+    let occupied: HashMap<IntVec3, usize> = cells
+        .iter()
+        .enumerate()
+        .map(|(index, &cell)| (cell, index))
+        .collect();
+    let Some(&first) = cells.first() else {
+        return Vec::new();
+    };
+
+    // Pad the bounds by one cell so the air surrounds the whole body
+    let (mut min, mut max) = (first, first);
+    for &(x, y, z) in cells {
+        min = (min.0.min(x), min.1.min(y), min.2.min(z));
+        max = (max.0.max(x), max.1.max(y), max.2.max(z));
+    }
+    let min = offset(min, (-1, -1, -1));
+    let max = offset(max, (1, 1, 1));
+    let in_bounds = |(x, y, z): IntVec3| {
+        (min.0..=max.0).contains(&x) && (min.1..=max.1).contains(&y) && (min.2..=max.2).contains(&z)
+    };
+
+    // Flood the air from a corner, which is always empty
+    let mut outside = HashSet::from([min]);
+    let mut queue = VecDeque::from([min]);
+    while let Some(cell) = queue.pop_front() {
+        for direction in FACE_NEIGHBORS {
+            let next = offset(cell, direction);
+            if in_bounds(next) && !occupied.contains_key(&next) && outside.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    // Walk inwards from the particles touching the air, counting layers
+    let mut depths = vec![u32::MAX; cells.len()];
+    let mut queue = VecDeque::new();
+    for (index, &cell) in cells.iter().enumerate() {
+        let exposed = FACE_NEIGHBORS
+            .iter()
+            .any(|&direction| outside.contains(&offset(cell, direction)));
+        if exposed {
+            depths[index] = 1;
+            queue.push_back(index);
+        }
+    }
+    while let Some(index) = queue.pop_front() {
+        for direction in FACE_NEIGHBORS {
+            if let Some(&next) = occupied.get(&offset(cells[index], direction)) {
+                if depths[next] == u32::MAX {
+                    depths[next] = depths[index] + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    depths
+        .into_iter()
+        .map(|depth| depth > shell_thickness)
+        .collect()
+}
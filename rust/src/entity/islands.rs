@@ -0,0 +1,204 @@
+use super::{bones, neighbor_lists, volume, Entity, Particle};
+use anyhow::{Context, Result};
+use godot::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Islands of particles joined by active connections, kept up to date as connections are removed.
+// Union-find can't undo a union, so instead each removed connection searches outwards from both
+// ends, stopping once they meet or one side runs out. That costs at most the smaller side.
+pub struct IslandTracker {
+    // Active connections in both directions, as each is only stored on one end
+    adjacency: Vec<Vec<usize>>,
+    labels: Vec<usize>,
+    next_label: usize,
+    island_count: usize,
+}
+
+impl IslandTracker {
+    pub fn new(particles: &[Particle]) -> Self {
+        let adjacency = neighbor_lists(particles, |_, connection| connection.active);
+
+        // Label each island by flood fill
+        let mut labels = vec![usize::MAX; particles.len()];
+        let mut next_label = 0;
+        for start in 0..particles.len() {
+            if labels[start] != usize::MAX {
+                continue;
+            }
+            labels[start] = next_label;
+            let mut queue = VecDeque::from([start]);
+            while let Some(index) = queue.pop_front() {
+                for &next in &adjacency[index] {
+                    if labels[next] == usize::MAX {
+                        labels[next] = next_label;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            next_label += 1;
+        }
+
+        Self {
+            adjacency,
+            labels,
+            next_label,
+            island_count: next_label,
+        }
+    }
+
+    pub const fn island_count(&self) -> usize {
+        self.island_count
+    }
+
+    pub fn island_of(&self, index: usize) -> usize {
+        self.labels[index]
+    }
+
+    // Remove a connection, splitting its island if that was the last path between the ends
+    pub fn disconnect(&mut self, a: usize, b: usize) {
+        for (from, to) in [(a, b), (b, a)] {
+            if let Some(position) = self.adjacency[from].iter().position(|&next| next == to) {
+                self.adjacency[from].swap_remove(position);
+            }
+        }
+        if a == b || self.labels[a] != self.labels[b] {
+            return;
+        }
+        if let Some(separated) = self.separated_side(a, b) {
+            for index in separated {
+                self.labels[index] = self.next_label;
+            }
+            self.next_label += 1;
+            self.island_count += 1;
+        }
+    }
+
+    // Search from both ends in lockstep, returning the particles on whichever side runs out first
+    fn separated_side(&self, a: usize, b: usize) -> Option<Vec<usize>> {
+        let mut searches = [Search::new(a), Search::new(b)];
+        loop {
+            for side in 0..2 {
+                let Some(index) = searches[side].queue.pop_front() else {
+                    return Some(searches[side].visited.drain().collect());
+                };
+                for &next in &self.adjacency[index] {
+                    if searches[1 - side].visited.contains(&next) {
+                        return None;
+                    }
+                    if searches[side].visited.insert(next) {
+                        searches[side].queue.push_back(next);
+                    }
+                }
+            }
+        }
+    }
+
+    // Particle indices of each island, each sorted by index
+    pub fn islands(&self) -> Vec<Vec<usize>> {
+        let mut islands: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, &label) in self.labels.iter().enumerate() {
+            islands.entry(label).or_default().push(index);
+        }
+        islands.into_values().collect()
+    }
+}
+
+struct Search {
+    queue: VecDeque<usize>,
+    visited: HashSet<usize>,
+}
+
+impl Search {
+    fn new(start: usize) -> Self {
+        Self {
+            queue: VecDeque::from([start]),
+            visited: HashSet::from([start]),
+        }
+    }
+}
+
+impl Entity {
+    // Move every island no longer connected to the central particle into its own entity
+    pub fn split_islands(&mut self) -> Result<()> {
+        if self.islands.island_count() <= 1 {
+            return Ok(());
+        }
+
+        let mut parent = self
+            .base
+            .get_parent()
+            .context("Entity has no parent to add chunks to")?;
+        let transform = self.base.get_transform();
+
+        let central_island = self.islands.island_of(self.central_particle);
+        let mut main_island = Vec::new();
+        for island in self.islands.islands() {
+            if self.islands.island_of(island[0]) == central_island {
+                main_island = island;
+                continue;
+            }
+            // Dead particles fall away rather than becoming entities of their own
+            if island.iter().all(|&index| !self.particles[index].alive) {
+                continue;
+            }
+
+            let mut chunk = Gd::<Self>::new_default();
+            {
+                let mut chunk = chunk.bind_mut();
+                chunk.base.set_transform(transform);
+                chunk.particles = extract_island(&self.particles, &island);
+                chunk.central_particle = 0;
+                chunk.copy_settings_from(self);
+            }
+            // Deferred as the parent may be busy processing its children
+            parent.call_deferred("add_child".into(), &[chunk.to_variant()]);
+            godot_print!("Split off chunk of {} particles", island.len());
+
+            #[allow(clippy::cast_possible_wrap)]
+            let particle_indices: Vec<i64> = island.iter().map(|&index| index as i64).collect();
+            self.emit_deferred(
+                "chunk_detached",
+                &[PackedInt64Array::from(particle_indices.as_slice()).to_variant()],
+            );
+        }
+
+        // Particle indices are about to change, so let go of any dragged particle
+        self.drag = None;
+
+        // Keep the island holding the central particle
+        self.central_particle = main_island
+            .iter()
+            .position(|&index| index == self.central_particle)
+            .unwrap_or(0);
+        self.particles = extract_island(&self.particles, &main_island);
+        self.volume_clusters = volume::build_volume_clusters(&self.particles);
+        self.bone_groups = bones::build_bone_groups(&self.particles);
+        self.islands = IslandTracker::new(&self.particles);
+        self.mark_topology_dirty();
+        Ok(())
+    }
+}
+
+// Copy the island's particles out, remapping connections and dropping those that leave it
+fn extract_island(particles: &[Particle], island: &[usize]) -> Vec<Particle> {
+    let mut mapping = vec![None; particles.len()];
+    for (new_index, &old_index) in island.iter().enumerate() {
+        mapping[old_index] = Some(new_index);
+    }
+
+    island
+        .iter()
+        .map(|&old_index| {
+            let mut particle = particles[old_index].clone();
+            particle.connections.retain_mut(|connection| {
+                if let Some(new_index) = mapping[connection.target_index] {
+                    connection.target_index = new_index;
+                    true
+                } else {
+                    false
+                }
+            });
+            particle
+        })
+        .collect()
+}
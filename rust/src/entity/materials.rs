@@ -0,0 +1,317 @@
+use super::ParticleMaterial;
+use godot::{
+    engine::{Resource, ResourceVirtual},
+    prelude::*,
+};
+
+// Physical behavior of one particle material, as used by the solver and wound effects
+#[derive(Clone, Copy, PartialEq)]
+pub struct PhysicalProperties {
+    // Zero mass pins the particle in place
+    pub mass: f32,
+    // Inverse stiffness of connections, zero is perfectly rigid
+    pub compliance: f32,
+    // How easily a cluster gives up its volume when squashed
+    pub volume_compliance: f32,
+    // Fraction of velocity lost each step
+    pub damping: f32,
+    // Fraction a connection can stretch beyond its rest length before it starts to tear
+    pub break_strain: f32,
+    // Upward acceleration countering gravity
+    pub buoyancy: f32,
+    // Damage a particle takes before it dies
+    pub toughness: f32,
+    // Color of the debris sprayed from a wound
+    pub wound_color: Color,
+    // Number of debris particles per wound, zero for none
+    pub wound_debris: u32,
+    // How much nearby particles darken each time they are wounded
+    pub wound_darkening: f32,
+}
+
+impl PhysicalProperties {
+    pub const fn default_for(material: &ParticleMaterial) -> Self {
+        match material {
+            ParticleMaterial::Flesh => Self {
+                mass: 1.0,
+                compliance: 0.000_02,
+                volume_compliance: 0.000_1,
+                damping: 0.02,
+                break_strain: 0.5,
+                buoyancy: 0.0,
+                toughness: 1.0,
+                wound_color: Color {
+                    r: 0.5,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0,
+                },
+                wound_debris: 24,
+                wound_darkening: 0.2,
+            },
+            ParticleMaterial::Skin => Self {
+                mass: 1.0,
+                compliance: 0.000_01,
+                volume_compliance: 0.000_5,
+                damping: 0.02,
+                break_strain: 0.6,
+                buoyancy: 0.0,
+                toughness: 1.5,
+                wound_color: Color {
+                    r: 0.5,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0,
+                },
+                wound_debris: 16,
+                wound_darkening: 0.4,
+            },
+            ParticleMaterial::Bone => Self {
+                mass: 3.0,
+                compliance: 0.0,
+                volume_compliance: 0.0,
+                damping: 0.02,
+                break_strain: f32::INFINITY,
+                buoyancy: 0.0,
+                toughness: 5.0,
+                wound_color: Color {
+                    r: 0.9,
+                    g: 0.9,
+                    b: 0.85,
+                    a: 1.0,
+                },
+                wound_debris: 8,
+                wound_darkening: 0.05,
+            },
+            ParticleMaterial::Heart => Self {
+                mass: 1.0,
+                compliance: 0.000_03,
+                volume_compliance: 0.000_1,
+                damping: 0.02,
+                break_strain: 0.4,
+                buoyancy: 0.0,
+                toughness: 1.0,
+                wound_color: Color {
+                    r: 0.6,
+                    g: 0.0,
+                    b: 0.0,
+                    a: 1.0,
+                },
+                wound_debris: 48,
+                wound_darkening: 0.2,
+            },
+            ParticleMaterial::Wing => Self {
+                mass: 0.2,
+                compliance: 0.000_005,
+                volume_compliance: 0.01,
+                damping: 0.02,
+                break_strain: 0.8,
+                buoyancy: 0.0,
+                toughness: 0.8,
+                wound_color: Color {
+                    r: 0.2,
+                    g: 0.2,
+                    b: 0.5,
+                    a: 1.0,
+                },
+                wound_debris: 8,
+                wound_darkening: 0.3,
+            },
+        }
+    }
+
+    pub fn inverse_mass(&self) -> f32 {
+        if self.mass > 0.0 {
+            1.0 / self.mass
+        } else {
+            0.0
+        }
+    }
+}
+
+// Snapshot of the properties of every material, cheap to share with solver threads
+#[derive(Clone, Copy, PartialEq)]
+pub struct PropertiesTable {
+    flesh: PhysicalProperties,
+    skin: PhysicalProperties,
+    bone: PhysicalProperties,
+    heart: PhysicalProperties,
+    wing: PhysicalProperties,
+}
+
+impl Default for PropertiesTable {
+    fn default() -> Self {
+        Self {
+            flesh: PhysicalProperties::default_for(&ParticleMaterial::Flesh),
+            skin: PhysicalProperties::default_for(&ParticleMaterial::Skin),
+            bone: PhysicalProperties::default_for(&ParticleMaterial::Bone),
+            heart: PhysicalProperties::default_for(&ParticleMaterial::Heart),
+            wing: PhysicalProperties::default_for(&ParticleMaterial::Wing),
+        }
+    }
+}
+
+impl PropertiesTable {
+    pub const fn get(&self, material: &ParticleMaterial) -> &PhysicalProperties {
+        match material {
+            ParticleMaterial::Flesh => &self.flesh,
+            ParticleMaterial::Skin => &self.skin,
+            ParticleMaterial::Bone => &self.bone,
+            ParticleMaterial::Heart => &self.heart,
+            ParticleMaterial::Wing => &self.wing,
+        }
+    }
+
+    // Materials missing from the resource, and values not overridden, keep their defaults
+    pub fn from_resource(resource: Option<&Gd<MaterialTable>>) -> Self {
+        let mut table = Self::default();
+        if let Some(resource) = resource {
+            let resource = resource.bind();
+            let overrides = [
+                (&mut table.flesh, &resource.flesh),
+                (&mut table.skin, &resource.skin),
+                (&mut table.bone, &resource.bone),
+                (&mut table.heart, &resource.heart),
+                (&mut table.wing, &resource.wing),
+            ];
+            for (properties, material) in overrides {
+                if let Some(material) = material {
+                    material.bind().apply_to(properties);
+                }
+            }
+        }
+        table
+    }
+}
+
+// Editable properties of a single material. Each value only applies when its override is ticked,
+// the rest keep the defaults of whichever material the resource is assigned to.
+#[derive(GodotClass)]
+#[class(base=Resource)]
+pub struct MaterialProperties {
+    #[base]
+    base: Base<Resource>,
+    #[export]
+    override_mass: bool,
+    #[export]
+    mass: f32,
+    #[export]
+    override_compliance: bool,
+    #[export]
+    compliance: f32,
+    #[export]
+    override_volume_compliance: bool,
+    #[export]
+    volume_compliance: f32,
+    #[export]
+    override_damping: bool,
+    #[export]
+    damping: f32,
+    #[export]
+    override_break_strain: bool,
+    #[export]
+    break_strain: f32,
+    #[export]
+    override_buoyancy: bool,
+    #[export]
+    buoyancy: f32,
+    #[export]
+    override_toughness: bool,
+    #[export]
+    toughness: f32,
+    #[export]
+    override_wound_color: bool,
+    #[export]
+    wound_color: Color,
+    #[export]
+    override_wound_debris: bool,
+    #[export]
+    wound_debris: u32,
+    #[export]
+    override_wound_darkening: bool,
+    #[export]
+    wound_darkening: f32,
+}
+
+#[godot_api]
+impl ResourceVirtual for MaterialProperties {
+    fn init(base: Base<Resource>) -> Self {
+        let defaults = PhysicalProperties::default_for(&ParticleMaterial::Flesh);
+        Self {
+            base,
+            override_mass: false,
+            mass: defaults.mass,
+            override_compliance: false,
+            compliance: defaults.compliance,
+            override_volume_compliance: false,
+            volume_compliance: defaults.volume_compliance,
+            override_damping: false,
+            damping: defaults.damping,
+            override_break_strain: false,
+            break_strain: defaults.break_strain,
+            override_buoyancy: false,
+            buoyancy: defaults.buoyancy,
+            override_toughness: false,
+            toughness: defaults.toughness,
+            override_wound_color: false,
+            wound_color: defaults.wound_color,
+            override_wound_debris: false,
+            wound_debris: defaults.wound_debris,
+            override_wound_darkening: false,
+            wound_darkening: defaults.wound_darkening,
+        }
+    }
+}
+
+impl MaterialProperties {
+    fn apply_to(&self, properties: &mut PhysicalProperties) {
+        if self.override_mass {
+            properties.mass = self.mass;
+        }
+        if self.override_compliance {
+            properties.compliance = self.compliance;
+        }
+        if self.override_volume_compliance {
+            properties.volume_compliance = self.volume_compliance;
+        }
+        if self.override_damping {
+            properties.damping = self.damping;
+        }
+        if self.override_break_strain {
+            properties.break_strain = self.break_strain;
+        }
+        if self.override_buoyancy {
+            properties.buoyancy = self.buoyancy;
+        }
+        if self.override_toughness {
+            properties.toughness = self.toughness;
+        }
+        if self.override_wound_color {
+            properties.wound_color = self.wound_color;
+        }
+        if self.override_wound_debris {
+            properties.wound_debris = self.wound_debris;
+        }
+        if self.override_wound_darkening {
+            properties.wound_darkening = self.wound_darkening;
+        }
+    }
+}
+
+// Editable table assigning properties to each material, assigned to an entity in the inspector
+#[derive(GodotClass)]
+#[class(base=Resource, init)]
+pub struct MaterialTable {
+    #[base]
+    base: Base<Resource>,
+    #[export]
+    flesh: Option<Gd<MaterialProperties>>,
+    #[export]
+    skin: Option<Gd<MaterialProperties>>,
+    #[export]
+    bone: Option<Gd<MaterialProperties>>,
+    #[export]
+    heart: Option<Gd<MaterialProperties>>,
+    #[export]
+    wing: Option<Gd<MaterialProperties>>,
+}
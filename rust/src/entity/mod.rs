@@ -1,33 +1,57 @@
 use godot::{
     engine::{
-        global::Key, ImmediateMesh, InputEvent, InputEventKey, Mesh, MeshInstance3D, RigidBody3D,
-        RigidBody3DVirtual,
+        global::Key, InputEvent, InputEventKey, InputEventMouseButton, MeshInstance3D,
+        MultiMeshInstance3D, RigidBody3D, RigidBody3DVirtual,
     },
     prelude::*,
 };
-use std::collections::{HashMap, HashSet};
-
+use shapes::{Shape, ShapeType};
+use spatial::SpatialHash;
+use std::collections::HashSet;
+
+mod aero;
+mod anchors;
+mod bones;
+mod collision;
 mod cut;
+mod damage;
+mod drag;
+mod gpu;
+mod heart;
+mod interior;
+mod islands;
+mod materials;
 mod physics;
 mod render;
+mod shapes;
+mod spatial;
+mod state;
+mod surface;
+mod volume;
+mod world;
+mod wounds;
 
 const PARTICLE_DISTANCE: f32 = 0.1;
+// Upper bound on fixed steps per frame, so a slow frame can't spiral into ever more steps
+const MAX_STEPS_PER_FRAME: u32 = 8;
+// Number of consecutive over-stretched steps before a connection tears
+const TEAR_STEPS: u32 = 3;
 
 #[derive(PartialEq, Clone)]
 pub struct Connection {
     pub target_index: usize,
-    direction: Vector3,
     distance: f32,
     pub active: bool,
+    overstretched_steps: u32,
 }
 
 impl Connection {
-    const fn new(target_index: usize, direction: Vector3, distance: f32) -> Self {
+    const fn new(target_index: usize, distance: f32) -> Self {
         Self {
             target_index,
-            direction,
             distance,
             active: true,
+            overstretched_steps: 0,
         }
     }
 }
@@ -36,7 +60,16 @@ impl Connection {
 pub struct Particle {
     pub position: Vector3,
     old_position: Vector3,
+    // Position at the start of the last fixed step, used for render interpolation
+    previous_position: Vector3,
     pub interior: bool,
+    // Accumulated from strain, impacts and cuts, the particle dies once past its toughness
+    pub damage: f32,
+    pub alive: bool,
+    // How far the particle has darkened from nearby wounds, from 0 to 1
+    pub bruise: f32,
+    // Held in place regardless of material, set from scenes and scripts
+    pub anchored: bool,
     pub connections: Vec<Connection>,
     pub material: ParticleMaterial,
 }
@@ -46,11 +79,28 @@ impl Particle {
         Self {
             position,
             old_position: position,
+            previous_position: position,
             interior,
+            damage: 0.0,
+            alive: true,
+            bruise: 0.0,
+            anchored: false,
             connections: Vec::new(),
             material,
         }
     }
+
+    pub fn render_position(&self, alpha: f32) -> Vector3 {
+        self.previous_position.lerp(self.position, alpha)
+    }
+
+    pub fn inverse_mass(&self, properties: &materials::PropertiesTable) -> f32 {
+        if self.anchored {
+            0.0
+        } else {
+            properties.get(&self.material).inverse_mass()
+        }
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -62,18 +112,6 @@ pub enum ParticleMaterial {
     Wing,
 }
 
-enum ShapeType {
-    Box,
-    Ellipsoid,
-}
-
-struct Shape {
-    name: String,
-    material: ParticleMaterial,
-    transform: Transform3D,
-    shape_type: ShapeType,
-}
-
 #[derive(GodotClass)]
 #[class(base=RigidBody3D)]
 pub struct Entity {
@@ -81,10 +119,52 @@ pub struct Entity {
     base: Base<RigidBody3D>,
     pub particles: Vec<Particle>,
     central_particle: usize,
-
+    volume_clusters: Vec<volume::VolumeCluster>,
+    bone_groups: Vec<bones::BoneGroup>,
+    islands: islands::IslandTracker,
+
+    #[export]
+    time_step: f32,
+    #[export]
+    substeps: u32,
+    #[export]
+    material_table: Option<Gd<materials::MaterialTable>>,
+    #[export]
+    use_gpu: bool,
+    #[export]
+    collide_with_world: bool,
+    #[export]
+    air_density: f32,
+    gpu_solver: Option<gpu::GpuSolver>,
     accumulator: f32,
+
+    #[export]
+    heart_rate: f32,
+    #[export]
+    pulse_strength: f32,
+    #[export]
+    pulse_attenuation: f32,
+    heart_phase: f32,
+    heart_beating: bool,
+    alive: bool,
+
+    #[export]
+    hollow: bool,
+    #[export]
+    shell_thickness: u32,
+
+    #[export]
+    render_surface: bool,
+    #[export]
+    use_multimesh: bool,
+    render_mesh: Option<Gd<MeshInstance3D>>,
+    particle_instances: Option<Gd<MultiMeshInstance3D>>,
     plane_rotate: bool,
     plane_size: f32,
+
+    #[export]
+    allow_dragging: bool,
+    drag: Option<drag::Drag>,
 }
 
 #[godot_api]
@@ -94,11 +174,38 @@ impl RigidBody3DVirtual for Entity {
             base,
             particles: Vec::new(),
             central_particle: 0,
-
+            volume_clusters: Vec::new(),
+            bone_groups: Vec::new(),
+            islands: islands::IslandTracker::new(&[]),
+
+            time_step: 1.0 / 30.0,
+            substeps: 4,
+            material_table: None,
+            use_gpu: false,
+            collide_with_world: true,
+            air_density: 1.2,
+            gpu_solver: None,
             accumulator: 0.0,
 
+            heart_rate: 70.0,
+            pulse_strength: 0.5,
+            pulse_attenuation: 0.7,
+            heart_phase: 0.0,
+            heart_beating: false,
+            alive: true,
+
+            hollow: false,
+            shell_thickness: 1,
+
+            render_surface: true,
+            use_multimesh: true,
+            render_mesh: None,
+            particle_instances: None,
             plane_rotate: false,
             plane_size: 0.2,
+
+            allow_dragging: true,
+            drag: None,
         };
         instance.base.set_gravity_scale(0.0);
         instance.base.add_to_group("Entity".into());
@@ -107,7 +214,17 @@ impl RigidBody3DVirtual for Entity {
     }
 
     fn ready(&mut self) {
+        // Entities split off from another body arrive with their particles already set
+        if !self.particles.is_empty() {
+            self.volume_clusters = volume::build_volume_clusters(&self.particles);
+            self.bone_groups = bones::build_bone_groups(&self.particles);
+            self.islands = islands::IslandTracker::new(&self.particles);
+            self.add_render_meshes();
+            return;
+        }
+
         let mut local_particles = Vec::new();
+        let mut local_cells = Vec::new();
 
         let (shapes, grid_min, grid_max) = self.sort_shapes();
 
@@ -122,24 +239,7 @@ impl RigidBody3DVirtual for Entity {
                     let mut surface_distance = 0.0;
                     let mut particle_material = ParticleMaterial::Flesh;
                     for shape in &shapes {
-                        let local_position = shape.transform.affine_inverse() * position;
-
-                        let (inside, distance_to_edge) = match shape.shape_type {
-                            ShapeType::Box => {
-                                let dx = 0.5 - local_position.x.abs();
-                                let dy = 0.5 - local_position.y.abs();
-                                let dz = 0.5 - local_position.z.abs();
-                                let distance = dx.min(dy).min(dz);
-                                (distance > 0.0, distance)
-                            }
-                            ShapeType::Ellipsoid => {
-                                let norm_squared = local_position.x.powi(2)
-                                    + local_position.y.powi(2)
-                                    + local_position.z.powi(2);
-                                let distance = 0.5 - norm_squared.sqrt();
-                                (norm_squared < 0.25, distance)
-                            }
-                        };
+                        let (inside, distance_to_edge) = shape.sample(position);
                         if inside {
                             inside_shape = true;
                             particle_material = shape.material.clone();
@@ -157,6 +257,7 @@ impl RigidBody3DVirtual for Entity {
                     }
 
                     local_particles.push(Particle::new(position, particle_material, false));
+                    local_cells.push((x, y, z));
 
                     // Set the central particle
                     if x == 0 && y == 0 && z == 0 {
@@ -167,49 +268,78 @@ impl RigidBody3DVirtual for Entity {
         }
         godot_print!("Created {} particles", local_particles.len());
 
+        // Set interior particles, those buried under the outer shell, which is at least one deep
+        let interior = interior::find_interior(&local_cells, self.shell_thickness.max(1));
+        for (particle, interior) in local_particles.iter_mut().zip(interior) {
+            particle.interior = interior;
+        }
+        if self.hollow {
+            self.hollow_out(&mut local_particles);
+        }
+        if local_particles.is_empty() {
+            godot_print!("Entity has no particles");
+            self.add_render_meshes();
+            return;
+        }
+
         self.connect_particles(&mut local_particles);
 
-        // Set interior particles
-        // for particle in &mut local_particles {
-        //     if particle.connections.len() > 10 {
-        //         particle.interior = true;
-        //     }
-        // }
+        // Group particles with their neighbors to preserve volume
+        self.volume_clusters = volume::build_volume_clusters(&local_particles);
+        // Join connected bone into rigid parts
+        self.bone_groups = bones::build_bone_groups(&local_particles);
 
         // Replace the particles with the local version
         self.particles = local_particles;
+        self.islands = islands::IslandTracker::new(&self.particles);
 
-        // Add render mesh
-        let mut render_mesh = MeshInstance3D::new_alloc();
-        render_mesh.set_mesh(ImmediateMesh::new().upcast::<Mesh>());
-        self.base.add_child(render_mesh.upcast::<Node>());
+        self.add_render_meshes();
     }
 
-    #[allow(clippy::cast_possible_truncation)]
-    fn process(&mut self, delta: f64) {
-        self.accumulator += delta as f32;
+    fn process(&mut self, _delta: f64) {
         // If c is pressed, cut the connections that intersect with the plane
         if Input::singleton().is_key_pressed(Key::KEY_C) {
-            self.cut_on_plane().unwrap();
+            if let Err(e) = self.cut_on_plane() {
+                godot_print!("Failed to cut on plane {e}");
+            }
         }
 
-        // let time_step = 1.0 / 30.0;
-        // self.accumulator += delta as f32;
-        // if self.accumulator >= time_step {
-        //     // Physics step
-        //     let new_positions = physics::process_step(&self.particles, time_step);
-        //     for (index, position) in new_positions {
-        //         self.particles[index].old_position = self.particles[index].position;
-        //         self.particles[index].position = position;
-        //     }
-        //     self.accumulator -= time_step;
-        // }
+        if let Err(e) = self.update_drag_target() {
+            godot_print!("Failed to update drag target {e}");
+        }
 
         self.render_particles();
     }
 
+    // Stepped alongside the physics server, as the world can only be queried from here
+    #[allow(clippy::cast_possible_truncation)]
+    fn physics_process(&mut self, delta: f64) {
+        // A step that isn't positive would never drain the accumulator, so hold still
+        if self.time_step <= 0.0 {
+            return;
+        }
+
+        // Advance the physics in fixed steps, carrying the remainder over to the next tick
+        self.accumulator += delta as f32;
+        let mut steps = 0;
+        while self.accumulator >= self.time_step {
+            if steps == MAX_STEPS_PER_FRAME {
+                // Too far behind, drop the backlog rather than stalling
+                self.accumulator = 0.0;
+                break;
+            }
+            self.step_physics();
+            self.accumulator -= self.time_step;
+            steps += 1;
+        }
+    }
+
     fn input(&mut self, event: Gd<InputEvent>) {
-        if let Some(event_key) = event.try_cast::<InputEventKey>() {
+        if let Some(event_mouse) = event.clone().try_cast::<InputEventMouseButton>() {
+            if let Err(e) = self.handle_mouse_button(&event_mouse) {
+                godot_print!("Failed to handle mouse button {e}");
+            }
+        } else if let Some(event_key) = event.try_cast::<InputEventKey>() {
             if event_key.is_pressed() && event_key.get_keycode() == Key::KEY_SPACE {
                 self.plane_rotate = !self.plane_rotate;
             } else if event_key.is_pressed() && event_key.get_keycode() == Key::KEY_R {
@@ -221,9 +351,103 @@ impl RigidBody3DVirtual for Entity {
     }
 }
 
+#[godot_api]
+impl Entity {
+    // Emitted with the indices the particles had in this entity before they split off
+    #[signal]
+    fn chunk_detached(particle_indices: PackedInt64Array);
+
+    // Emitted once when the entity loses its last heart particle
+    #[signal]
+    fn heart_stopped();
+
+    #[func]
+    fn is_heart_beating(&self) -> bool {
+        self.heart_beating
+    }
+
+    // Emitted once when the heart is destroyed or severed from the body
+    #[signal]
+    fn died();
+
+    #[func]
+    fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    // Anchor every particle inside the global box, returning how many were anchored
+    #[func]
+    fn anchor_particles_in_box(&mut self, aabb: Aabb) -> i64 {
+        self.anchor_where(|position| aabb.has_point(position))
+    }
+
+    // Anchor the particle closest to the global point, if any lies within the radius
+    #[func]
+    fn anchor_nearest(&mut self, point: Vector3, radius: f32) -> bool {
+        self.anchor_nearest_particle(point, radius)
+    }
+
+    #[func]
+    fn release_anchors(&mut self) {
+        for particle in &mut self.particles {
+            particle.anchored = false;
+        }
+    }
+
+    // Save the particles and their connections, so a damaged body can be restored later
+    #[func]
+    fn save_state(&self, path: GodotString) -> bool {
+        match self.write_state(&path) {
+            Ok(()) => true,
+            Err(e) => {
+                godot_print!("Failed to save state {e}");
+                false
+            }
+        }
+    }
+
+    #[func]
+    fn load_state(&mut self, path: GodotString) -> bool {
+        match self.read_state(&path) {
+            Ok(()) => true,
+            Err(e) => {
+                godot_print!("Failed to load state {e}");
+                false
+            }
+        }
+    }
+}
+
 type IntVec3 = (i32, i32, i32);
 
 impl Entity {
+    // Emit once the current call returns, so handlers can call back into the entity
+    fn emit_deferred(&mut self, signal: &str, args: &[Variant]) {
+        let mut call_args = vec![StringName::from(signal).to_variant()];
+        call_args.extend_from_slice(args);
+        self.base.call_deferred("emit_signal".into(), &call_args);
+    }
+
+    // Carry the exported settings over to an entity split off from this one
+    fn copy_settings_from(&mut self, other: &Self) {
+        self.time_step = other.time_step;
+        self.substeps = other.substeps;
+        self.material_table = other.material_table.clone();
+        self.use_gpu = other.use_gpu;
+        self.collide_with_world = other.collide_with_world;
+        self.air_density = other.air_density;
+        self.heart_rate = other.heart_rate;
+        self.pulse_strength = other.pulse_strength;
+        self.pulse_attenuation = other.pulse_attenuation;
+        self.hollow = other.hollow;
+        self.shell_thickness = other.shell_thickness;
+        self.render_surface = other.render_surface;
+        self.use_multimesh = other.use_multimesh;
+        self.plane_rotate = other.plane_rotate;
+        self.plane_size = other.plane_size;
+        self.allow_dragging = other.allow_dragging;
+    }
+
     fn sort_shapes(&mut self) -> (Vec<Shape>, IntVec3, IntVec3) {
         // Get all meshes in entity
         let mut shapes = Vec::new();
@@ -236,41 +460,13 @@ impl Entity {
                 // Get the global transform of the mesh
                 let transform = mesh_instance.get_global_transform();
 
-                // Transform these local corners to world space
-                let corner_1 = transform * Vector3::new(-0.5, -0.5, -0.5);
-                let corner_2 = transform * Vector3::new(0.5, 0.5, 0.5);
-
-                // Update the global bounds.
-                bounds_min.x = f32::min(bounds_min.x, corner_1.x);
-                bounds_min.y = f32::min(bounds_min.y, corner_1.y);
-                bounds_min.z = f32::min(bounds_min.z, corner_1.z);
-                bounds_min.x = f32::min(bounds_min.x, corner_2.x);
-                bounds_min.y = f32::min(bounds_min.y, corner_2.y);
-                bounds_min.z = f32::min(bounds_min.z, corner_2.z);
-
-                bounds_max.x = f32::max(bounds_max.x, corner_1.x);
-                bounds_max.y = f32::max(bounds_max.y, corner_1.y);
-                bounds_max.z = f32::max(bounds_max.z, corner_1.z);
-                bounds_max.x = f32::max(bounds_max.x, corner_2.x);
-                bounds_max.y = f32::max(bounds_max.y, corner_2.y);
-                bounds_max.z = f32::max(bounds_max.z, corner_2.z);
-
-                let shape_type = match mesh_instance
-                    .get_mesh()
-                    .unwrap()
-                    .get_class()
-                    .to_string()
-                    .as_str()
-                {
-                    "BoxMesh" => ShapeType::Box,
-                    "SphereMesh" => ShapeType::Ellipsoid,
-                    _ => {
-                        godot_print!(
-                            "Unexpected mesh type for mesh_instance: {}",
-                            mesh_instance.get_name()
-                        );
-                        continue;
-                    }
+                let Some(shape_type) = mesh_instance.get_mesh().and_then(ShapeType::from_mesh)
+                else {
+                    godot_print!(
+                        "Unexpected mesh type for mesh_instance: {}",
+                        mesh_instance.get_name()
+                    );
+                    continue;
                 };
 
                 // Add shape
@@ -293,12 +489,24 @@ impl Entity {
                         }
                     };
 
-                    shapes.push(Shape {
+                    let shape = Shape {
                         name,
                         material,
                         transform,
                         shape_type,
-                    });
+                    };
+
+                    // Update the global bounds
+                    for corner in shape.corners() {
+                        bounds_min.x = f32::min(bounds_min.x, corner.x);
+                        bounds_min.y = f32::min(bounds_min.y, corner.y);
+                        bounds_min.z = f32::min(bounds_min.z, corner.z);
+                        bounds_max.x = f32::max(bounds_max.x, corner.x);
+                        bounds_max.y = f32::max(bounds_max.y, corner.y);
+                        bounds_max.z = f32::max(bounds_max.z, corner.z);
+                    }
+
+                    shapes.push(shape);
                 } else {
                     godot_print!(
                         "Unexpected name format for mesh_instance: {}",
@@ -339,18 +547,27 @@ impl Entity {
         (shapes, grid_min, grid_max)
     }
 
+    // Remove the interior particles, keeping the central particle on the nearest remaining one
+    fn hollow_out(&mut self, local_particles: &mut Vec<Particle>) {
+        let Some(central_position) = local_particles
+            .get(self.central_particle)
+            .map(|particle| particle.position)
+        else {
+            return;
+        };
+        local_particles.retain(|particle| !particle.interior);
+        self.central_particle =
+            nearest_particle(local_particles, central_position, |_| true).unwrap_or(0);
+        godot_print!("Hollowed out to {} particles", local_particles.len());
+    }
+
     fn connect_particles(&mut self, local_particles: &mut [Particle]) {
         // Create grid for lookup
         let cell_size = PARTICLE_DISTANCE * 1.5;
-        let mut grid: HashMap<(isize, isize), Vec<usize>> = HashMap::new();
-        for (index, particle) in local_particles.iter().enumerate() {
-            #[allow(clippy::cast_possible_truncation)]
-            let (cell_x, cell_y) = (
-                (particle.position.x / cell_size) as isize,
-                (particle.position.y / cell_size) as isize,
-            );
-            grid.entry((cell_x, cell_y)).or_default().push(index);
-        }
+        let grid = SpatialHash::from_positions(
+            cell_size,
+            local_particles.iter().map(|particle| particle.position),
+        );
 
         // Connect particles, starting from central particle expanding outwards
         let mut total_connections = 0;
@@ -371,52 +588,35 @@ impl Entity {
 
                 // Connect the particle to nearby particles
                 let particle = &local_particles[particle_index];
-                let particle_pos = particle.position;
 
                 // Get nearby particles
-                #[allow(clippy::cast_possible_truncation)]
-                let (cell_x, cell_y) = (
-                    (particle.position.x / cell_size) as isize,
-                    (particle.position.y / cell_size) as isize,
-                );
-                for dx in -1..=1 {
-                    for dy in -1..=1 {
-                        if let Some(neighbors) = grid.get(&(cell_x + dx, cell_y + dy)) {
-                            for &other_index in neighbors {
-                                if particle_index == other_index {
-                                    continue;
-                                }
-                                let other_particle = &local_particles[other_index];
-                                let other_particle_pos = other_particle.position;
-                                let distance = particle.position.distance_to(other_particle_pos);
-                                if distance > cell_size {
-                                    continue;
-                                }
-
-                                // Check if that particle already has a connection to the current particle
-                                let has_connection = other_particle
-                                    .connections
-                                    .iter()
-                                    .any(|connection| connection.target_index == particle_index);
-
-                                if !has_connection {
-                                    // Connect the particles
-                                    let direction =
-                                        (other_particle_pos - particle_pos).normalized();
-
-                                    new_connections.push((
-                                        particle_index,
-                                        Connection::new(other_index, direction, distance),
-                                    ));
-
-                                    total_connections += 1;
-
-                                    // Add the other particle to the next list
-                                    if !processed_particles.contains(&other_index) {
-                                        next_next.insert(other_index);
-                                    }
-                                }
-                            }
+                for other_index in grid.neighbors(particle.position) {
+                    if particle_index == other_index {
+                        continue;
+                    }
+                    let other_particle = &local_particles[other_index];
+                    let other_particle_pos = other_particle.position;
+                    let distance = particle.position.distance_to(other_particle_pos);
+                    if distance > cell_size {
+                        continue;
+                    }
+
+                    // Check if that particle already has a connection to the current particle
+                    let has_connection = other_particle
+                        .connections
+                        .iter()
+                        .any(|connection| connection.target_index == particle_index);
+
+                    if !has_connection {
+                        // Connect the particles
+                        new_connections
+                            .push((particle_index, Connection::new(other_index, distance)));
+
+                        total_connections += 1;
+
+                        // Add the other particle to the next list
+                        if !processed_particles.contains(&other_index) {
+                            next_next.insert(other_index);
                         }
                     }
                 }
@@ -433,3 +633,66 @@ impl Entity {
         godot_print!("Connected particles: {}", total_connections);
     }
 }
+
+// A connection as seen from either of its ends
+#[derive(Clone, Copy)]
+pub struct Link {
+    pub neighbor: usize,
+    // Particle the connection is stored on, and its index there
+    pub owner: usize,
+    pub connection: usize,
+}
+
+// Connections are stored on one end only, so gather those passing the filter in both directions
+pub fn neighbor_links(
+    particles: &[Particle],
+    filter: impl Fn(usize, &Connection) -> bool,
+) -> Vec<Vec<Link>> {
+    let mut links: Vec<Vec<Link>> = vec![Vec::new(); particles.len()];
+    for (i, particle) in particles.iter().enumerate() {
+        for (c, connection) in particle.connections.iter().enumerate() {
+            if filter(i, connection) {
+                let j = connection.target_index;
+                links[i].push(Link {
+                    neighbor: j,
+                    owner: i,
+                    connection: c,
+                });
+                links[j].push(Link {
+                    neighbor: i,
+                    owner: i,
+                    connection: c,
+                });
+            }
+        }
+    }
+    links
+}
+
+pub fn neighbor_lists(
+    particles: &[Particle],
+    filter: impl Fn(usize, &Connection) -> bool,
+) -> Vec<Vec<usize>> {
+    neighbor_links(particles, filter)
+        .into_iter()
+        .map(|links| links.into_iter().map(|link| link.neighbor).collect())
+        .collect()
+}
+
+// Index of the particle passing the filter closest to the position
+pub fn nearest_particle(
+    particles: &[Particle],
+    position: Vector3,
+    filter: impl Fn(&Particle) -> bool,
+) -> Option<usize> {
+    particles
+        .iter()
+        .enumerate()
+        .filter(|(_, particle)| filter(particle))
+        .min_by(|(_, a), (_, b)| {
+            a.position
+                .distance_squared_to(position)
+                .total_cmp(&b.position.distance_squared_to(position))
+        })
+        .map(|(index, _)| index)
+}
@@ -1,66 +1,297 @@
-use super::{Particle, PARTICLE_DISTANCE};
-use crate::entity::ParticleMaterial;
-use godot::prelude::*;
+use super::{
+    bones::{build_bone_groups, solve_bone_group, BoneGroup},
+    gpu::GpuSolver,
+    materials::PropertiesTable,
+    spatial::SpatialHash,
+    volume::{solve_volume_cluster, VolumeCluster},
+    world::ContactPlane,
+    Entity, Particle, PARTICLE_DISTANCE, TEAR_STEPS,
+};
+use godot::{engine::Engine, prelude::*};
 use rand::seq::SliceRandom;
 use rayon::prelude::*;
-use std::{collections::HashMap, sync::Mutex};
+use std::sync::Mutex;
 
-#[allow(clippy::too_many_lines)]
-pub fn process_step(particles: &Vec<Particle>, delta: f32) -> Vec<(usize, Vector3)> {
-    let start_time = std::time::Instant::now();
+// Bounce off the floor, 1 is perfectly elastic, 0 is no bounce
+pub const RESTITUTION: f32 = 0.7;
+
+impl Entity {
+    // Advance the simulation by one fixed time step, split into substeps.
+    pub fn step_physics(&mut self) {
+        for particle in &mut self.particles {
+            particle.previous_position = particle.position;
+        }
+
+        // Create the GPU solver on first use, falling back to the CPU if it isn't available
+        if self.use_gpu && self.gpu_solver.is_none() {
+            match GpuSolver::new() {
+                Ok(solver) => self.gpu_solver = Some(solver),
+                Err(e) => {
+                    godot_print!("Failed to create GPU solver, using CPU {e}");
+                    self.use_gpu = false;
+                }
+            }
+        }
+
+        let properties = PropertiesTable::from_resource(self.material_table.as_ref());
+
+        // Contacts with the physics world are found once per step and held through the substeps
+        let contacts = if self.collide_with_world {
+            self.gather_world_contacts().unwrap_or_else(|e| {
+                godot_print!("Failed to gather world contacts {e}");
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        // Cutting through a bone splits its group into separate rigid parts
+        if !self
+            .bone_groups
+            .iter()
+            .all(|group| group.is_intact(&self.particles))
+        {
+            self.bone_groups = build_bone_groups(&self.particles);
+        }
+
+        let substeps = self.substeps.max(1);
+        #[allow(clippy::cast_precision_loss)]
+        let substep_delta = self.time_step / substeps as f32;
+        self.beat_heart(substep_delta);
+        // Air forces depend on velocity, so are taken from the end of the last step and held
+        let external_accelerations = self.wing_accelerations(&properties, substep_delta);
+        let velocities_before = self.particle_velocities(substep_delta);
+        for _ in 0..substeps {
+            let new_positions = match &mut self.gpu_solver {
+                Some(solver) if self.use_gpu => {
+                    // The shader has no world contacts, volumes or bones, so fit them afterwards
+                    let mut positions: Vec<Vector3> = self
+                        .particles
+                        .iter()
+                        .map(|particle| particle.position)
+                        .collect();
+                    for (index, position) in solver.process_step(
+                        &self.particles,
+                        &properties,
+                        &external_accelerations,
+                        !self.collide_with_world,
+                        substep_delta,
+                    ) {
+                        positions[index] = position;
+                    }
+                    for (position, contact) in positions.iter_mut().zip(&contacts) {
+                        if let Some(contact) = contact {
+                            *position = contact.project(*position);
+                        }
+                    }
+                    let inverse_masses: Vec<f32> = self
+                        .particles
+                        .iter()
+                        .map(|particle| particle.inverse_mass(&properties))
+                        .collect();
+                    for cluster in &self.volume_clusters {
+                        if cluster.is_active(&self.particles) {
+                            // A single pass, so there is no multiplier to carry over
+                            solve_volume_cluster(
+                                cluster,
+                                &mut positions,
+                                &inverse_masses,
+                                cluster.compliance(&self.particles, &properties),
+                                &mut 0.0,
+                                substep_delta,
+                            );
+                        }
+                    }
+                    for group in &mut self.bone_groups {
+                        solve_bone_group(group, &mut positions, &inverse_masses);
+                    }
+                    positions.into_iter().enumerate().collect()
+                }
+                _ => process_step(
+                    &self.particles,
+                    &self.volume_clusters,
+                    &mut self.bone_groups,
+                    &contacts,
+                    &external_accelerations,
+                    !self.collide_with_world,
+                    &properties,
+                    substep_delta,
+                ),
+            };
+            for (index, position) in new_positions {
+                self.particles[index].old_position = self.particles[index].position;
+                self.particles[index].position = position;
+            }
+            self.apply_drag(&properties, substep_delta);
+        }
+
+        self.collide_with_entities(&properties);
+        self.accumulate_damage(&properties, &velocities_before, substep_delta);
+        self.tear_overstretched_connections(&properties);
+        self.kill_damaged_particles(&properties);
+        if let Err(e) = self.split_islands() {
+            godot_print!("Failed to split islands {e}");
+        }
+    }
+
+    // Deactivate connections that have stayed past their break strain for too long
+    fn tear_overstretched_connections(&mut self, properties: &PropertiesTable) {
+        // Find which connections are currently over-stretched
+        let mut overstretched = Vec::new();
+        for (i, particle) in self.particles.iter().enumerate() {
+            for (c, connection) in particle.connections.iter().enumerate() {
+                if !connection.active {
+                    continue;
+                }
+                let other = &self.particles[connection.target_index];
+                let break_strain = f32::min(
+                    properties.get(&particle.material).break_strain,
+                    properties.get(&other.material).break_strain,
+                );
+                let current_distance = particle.position.distance_to(other.position);
+                let strain = (current_distance - connection.distance) / connection.distance;
+                overstretched.push((i, c, strain > break_strain));
+            }
+        }
+
+        // Tear connections once they have been over-stretched for long enough
+        let mut torn_connections = Vec::new();
+        for (i, c, is_overstretched) in overstretched {
+            let connection = &mut self.particles[i].connections[c];
+            if is_overstretched {
+                connection.overstretched_steps += 1;
+                if connection.overstretched_steps >= TEAR_STEPS {
+                    connection.active = false;
+                    let target_index = connection.target_index;
+                    self.expose(i, target_index);
+                    self.islands.disconnect(i, target_index);
+                    torn_connections.push((i, target_index));
+                }
+            } else {
+                connection.overstretched_steps = 0;
+            }
+        }
+        if !torn_connections.is_empty() {
+            godot_print!("Torn connections: {}", torn_connections.len());
+            self.mark_topology_dirty();
+            self.wound(&torn_connections, properties);
+        }
+    }
+
+    // Connections have changed, so the GPU solver has to upload them again
+    pub fn mark_topology_dirty(&mut self) {
+        if let Some(solver) = &mut self.gpu_solver {
+            solver.mark_topology_dirty();
+        }
+    }
+
+    // Particles either side of a cut or tear are no longer buried inside the body
+    pub fn expose(&mut self, a: usize, b: usize) {
+        self.particles[a].interior = false;
+        self.particles[b].interior = false;
+    }
+
+    // How far between the last two fixed steps the current frame is, counting the time since the
+    // last physics tick as the accumulator only advances on ticks.
+    pub fn interpolation_alpha(&self) -> f32 {
+        if self.time_step > 0.0 {
+            let engine = Engine::singleton();
+            #[allow(clippy::cast_precision_loss)]
+            let tick = 1.0 / engine.get_physics_ticks_per_second().max(1) as f32;
+            #[allow(clippy::cast_possible_truncation)]
+            let since_tick = engine.get_physics_interpolation_fraction() as f32 * tick;
+            ((self.accumulator + since_tick) / self.time_step).clamp(0.0, 1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines, clippy::too_many_arguments)]
+pub fn process_step(
+    particles: &Vec<Particle>,
+    volume_clusters: &[VolumeCluster],
+    bone_groups: &mut [BoneGroup],
+    contacts: &[Option<ContactPlane>],
+    external_accelerations: &[Vector3],
+    // The physics world replaces the floor when colliding with it
+    use_floor: bool,
+    properties: &PropertiesTable,
+    delta: f32,
+) -> Vec<(usize, Vector3)> {
     let gravity = Vector3::new(0.0, -10.0, 0.0);
 
     // Create grid for lookup
-    let cell_size = PARTICLE_DISTANCE;
-    let mut grid: HashMap<(isize, isize), Vec<usize>> = HashMap::new();
-    for (index, particle) in particles.iter().enumerate() {
-        #[allow(clippy::cast_possible_truncation)]
-        let (cell_x, cell_y) = (
-            (particle.position.x / cell_size) as isize,
-            (particle.position.y / cell_size) as isize,
-        );
-        grid.entry((cell_x, cell_y)).or_default().push(index);
-    }
+    let grid = SpatialHash::from_positions(
+        PARTICLE_DISTANCE,
+        particles.iter().map(|particle| particle.position),
+    );
+
+    // Anchored particles are pinned with zero inverse mass, so constraints never move them
+    let inverse_masses: Vec<f32> = particles
+        .iter()
+        .map(|particle| particle.inverse_mass(properties))
+        .collect();
 
     // 1. Prediction Phase
-    let damping = 0.98;
     let mut predicted_positions: Vec<Vector3> = Vec::with_capacity(particles.len());
-    for particle in particles {
-        let velocity = (particle.position - particle.old_position) * damping;
+    for (i, particle) in particles.iter().enumerate() {
+        if inverse_masses[i] <= 0.0 {
+            predicted_positions.push(particle.position);
+            continue;
+        }
+        let material = properties.get(&particle.material);
+        let velocity = (particle.position - particle.old_position) * (1.0 - material.damping);
 
-        let mut acceleration = gravity;
+        let acceleration =
+            gravity + Vector3::new(0.0, material.buoyancy, 0.0) + external_accelerations[i];
 
-        // Wing uplift
-        if particle.material == ParticleMaterial::Wing {
-            let wing_uplift_strength = 50.0;
-            acceleration += Vector3::new(0.0, wing_uplift_strength, 0.0);
-        }
+        predicted_positions.push(particle.position + velocity + acceleration * delta * delta);
+    }
 
-        let mut new_position = particle.position + velocity + acceleration * delta * delta;
-        if particle.material == ParticleMaterial::Bone {
-            new_position = particle.position;
+    // Gather distance constraints from the active connections, compliance is the mean of both ends
+    let mut constraints = Vec::new();
+    for (i, particle) in particles.iter().enumerate() {
+        for connection in &particle.connections {
+            if connection.active {
+                let j = connection.target_index;
+                let compliance = (properties.get(&particle.material).compliance
+                    + properties.get(&particles[j].material).compliance)
+                    * 0.5;
+                constraints.push((i, j, connection.distance, compliance));
+            }
         }
-        predicted_positions.push(new_position);
     }
+    // Lagrange multipliers, accumulated over the iterations of this step
+    let mut lambdas = vec![0.0; constraints.len()];
+
+    // Volume clusters with a cut or torn connection no longer enclose anything
+    let volume_constraints: Vec<(&VolumeCluster, f32)> = volume_clusters
+        .iter()
+        .filter(|cluster| cluster.is_active(particles))
+        .map(|cluster| (cluster, cluster.compliance(particles, properties)))
+        .collect();
+    let mut volume_lambdas = vec![0.0; volume_constraints.len()];
 
     // Shuffle indices for randomized processing
     let mut indices: Vec<usize> = (0..particles.len()).collect();
     indices.shuffle(&mut rand::thread_rng());
+    let mut constraint_order: Vec<usize> = (0..constraints.len()).collect();
+    constraint_order.shuffle(&mut rand::thread_rng());
 
     // 2. Constraint Phase
     let solver_iterations: i32 = 4;
     let mut corrected_positions = predicted_positions.clone();
     for _ in 0..solver_iterations {
         // Collision constraints
-        let restitution = 0.7; // 1 is perfectly elastic, 0 is no bounce
         for &i in &indices {
-            if corrected_positions[i].y < 0.0 {
+            if use_floor && inverse_masses[i] > 0.0 && corrected_positions[i].y < 0.0 {
                 let penetration = 0.0 - corrected_positions[i].y;
 
                 let approach_velocity =
                     (predicted_positions[i] - particles[i].old_position).y / delta;
                 let bounce_velocity = if approach_velocity < 0.0 {
-                    -approach_velocity * restitution
+                    -approach_velocity * RESTITUTION
                 } else {
                     0.0
                 };
@@ -70,39 +301,45 @@ pub fn process_step(particles: &Vec<Particle>, delta: f32) -> Vec<(usize, Vector
             }
         }
 
-        // Self-collision constraints
+        // World collision constraints, against the contacts gathered for this step
+        for (i, contact) in contacts.iter().enumerate() {
+            if let Some(contact) = contact {
+                if inverse_masses[i] > 0.0 {
+                    corrected_positions[i] = contact.project(corrected_positions[i]);
+                }
+            }
+        }
+
+        // Self-collision constraints, rigid contacts split by inverse mass
         let minimum_distance = PARTICLE_DISTANCE;
-        let stiffness = 0.2;
         let corrections: Mutex<Vec<(usize, Vector3)>> = Mutex::new(Vec::new());
 
         indices.par_iter().for_each(|&particle_index| {
             let position = corrected_positions[particle_index];
             let mut local_corrections = Vec::new();
 
-            #[allow(clippy::cast_possible_truncation)]
-            let (cell_x, cell_y) = (
-                (position.x / cell_size) as isize,
-                (position.y / cell_size) as isize,
-            );
-            for dx in -1..=1 {
-                for dy in -1..=1 {
-                    if let Some(neighbors) = grid.get(&(cell_x + dx, cell_y + dy)) {
-                        for &other_index in neighbors {
-                            if particle_index == other_index {
-                                continue;
-                            }
-
-                            let dir = corrected_positions[other_index] - position;
-                            let current_distance = dir.length();
-                            if current_distance < minimum_distance {
-                                let correction = (dir / current_distance)
-                                    * ((minimum_distance - current_distance) * 0.5)
-                                    * stiffness;
-                                local_corrections.push((particle_index, -correction));
-                                local_corrections.push((other_index, correction));
-                            }
-                        }
-                    }
+            for other_index in grid.neighbors(position) {
+                // Each pair is visited from both sides, only resolve it once
+                if particle_index >= other_index {
+                    continue;
+                }
+                // Buried particles are held apart by their connections alone
+                if particles[particle_index].interior && particles[other_index].interior {
+                    continue;
+                }
+                let w1 = inverse_masses[particle_index];
+                let w2 = inverse_masses[other_index];
+                if w1 + w2 <= 0.0 {
+                    continue;
+                }
+
+                let dir = corrected_positions[other_index] - position;
+                let current_distance = dir.length();
+                if current_distance > 0.0 && current_distance < minimum_distance {
+                    let correction = (dir / current_distance)
+                        * ((minimum_distance - current_distance) / (w1 + w2));
+                    local_corrections.push((particle_index, -correction * w1));
+                    local_corrections.push((other_index, correction * w2));
                 }
             }
             // Extend the shared corrections with local results
@@ -116,54 +353,51 @@ pub fn process_step(particles: &Vec<Particle>, delta: f32) -> Vec<(usize, Vector
             corrected_positions[*index] += *correction;
         }
 
-        // Spring constraints
-        let stiffness = 0.1;
-        for &i in &indices {
-            let particle = &particles[i];
-
-            for connection in &particle.connections {
-                if connection.active {
-                    // let j = connection.target_index;
-                    // let offset = corrected_positions[j] - corrected_positions[i];
-                    // let direction = offset.normalized();
-                    // let current_distance = offset.length();
-                    // let difference = current_distance - connection.distance;
-                    // let correction = direction * (difference * 0.5) * stiffness;
-
-                    // // Adjust the positions of both particles
-                    // corrected_positions[i] += correction;
-                    // corrected_positions[j] -= correction;
-
-                    let j = connection.target_index;
+        // Distance constraints (XPBD)
+        for &c in &constraint_order {
+            let (i, j, rest_distance, compliance) = constraints[c];
+            let w1 = inverse_masses[i];
+            let w2 = inverse_masses[j];
 
-                    // Compute the offset and its direction
-                    let offset = corrected_positions[j] - corrected_positions[i];
-                    let direction = offset.normalized();
-
-                    // Compute how much the current direction deviates from the desired direction
-                    let direction_difference = direction - connection.direction.normalized();
+            let offset = corrected_positions[i] - corrected_positions[j];
+            let current_distance = offset.length();
+            if current_distance <= f32::EPSILON {
+                continue;
+            }
 
-                    // Calculate the correction based on the direction difference
-                    let directional_correction = direction_difference * stiffness;
+            // Compliance is scaled by the time step so stiffness doesn't depend on it
+            let alpha = compliance / (delta * delta);
+            let denominator = w1 + w2 + alpha;
+            if denominator <= f32::EPSILON {
+                continue;
+            }
+            let constraint = current_distance - rest_distance;
+            let delta_lambda = (-constraint - alpha * lambdas[c]) / denominator;
+            lambdas[c] += delta_lambda;
 
-                    // Calculate the stretching or compressing of the spring
-                    let current_distance = offset.length();
-                    let distance_difference = current_distance - connection.distance;
-                    let stretch_correction = direction * (distance_difference * 0.5) * stiffness;
+            let correction = (offset / current_distance) * delta_lambda;
+            corrected_positions[i] += correction * w1;
+            corrected_positions[j] -= correction * w2;
+        }
 
-                    // Combine the corrections
-                    let total_correction = stretch_correction + directional_correction;
+        // Volume constraints (XPBD)
+        for (c, &(cluster, compliance)) in volume_constraints.iter().enumerate() {
+            solve_volume_cluster(
+                cluster,
+                &mut corrected_positions,
+                &inverse_masses,
+                compliance,
+                &mut volume_lambdas[c],
+                delta,
+            );
+        }
 
-                    // Adjust the positions of both particles
-                    corrected_positions[i] += total_correction;
-                    corrected_positions[j] -= total_correction;
-                }
-            }
+        // Bone shape matching, last so the bones stay rigid against the flesh pulling on them
+        for group in bone_groups.iter_mut() {
+            solve_bone_group(group, &mut corrected_positions, &inverse_masses);
         }
     }
 
-    godot_print!("Physics step took {}ms", start_time.elapsed().as_millis());
-
     // 3. Update Phase
     corrected_positions
         .iter()
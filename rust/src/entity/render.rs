@@ -1,12 +1,18 @@
 use super::cut::{line_intersects_finite_plane, render_cut_plane};
-use super::{Entity, ParticleMaterial};
+use super::{surface::extract_surface, Entity, Particle, ParticleMaterial};
 use anyhow::{Context, Result};
 use godot::{
-    engine::{mesh::PrimitiveType, ImmediateMesh, Material, MeshInstance3D, ResourceLoader},
+    engine::{
+        mesh::PrimitiveType, multi_mesh::TransformFormat, ImmediateMesh, Material, Mesh,
+        MeshInstance3D, MultiMesh, MultiMeshInstance3D, ResourceLoader, SphereMesh,
+    },
     prelude::*,
 };
 use std::str::FromStr;
 
+// Radius of the debug particle markers
+const PARTICLE_MARKER_SIZE: f32 = 0.05;
+
 impl Entity {
     #[allow(clippy::too_many_lines)]
     pub fn render_particles(&mut self) {
@@ -19,55 +25,50 @@ impl Entity {
         };
         let transform = self.base.get_global_transform();
         let transform_inv = transform.affine_inverse();
+        let alpha = self.interpolation_alpha();
 
         render_geometry.clear_surfaces();
 
-        // Load material
-        let material = ResourceLoader::singleton()
-            .load(GodotString::from_str("res://debug_node.tres").unwrap())
-            .unwrap()
-            .cast::<Material>();
+        let material = load_debug_material();
 
-        // Render particles
-        render_geometry.call(
-            StringName::from("surface_begin"),
-            &[
-                Variant::from(PrimitiveType::PRIMITIVE_TRIANGLES),
-                Variant::from(material.clone()),
-            ],
-        );
-        let diamond_size = 0.05;
-        let faces = vec![
-            (Vector3::FORWARD, Vector3::RIGHT, Vector3::UP), // Top Front Right Face
-            (Vector3::UP, Vector3::LEFT, Vector3::FORWARD),  // Top Front Left Face
-            (Vector3::BACK, Vector3::LEFT, Vector3::UP),     // Top Back Left Face
-            (Vector3::UP, Vector3::RIGHT, Vector3::BACK),    // Top Back Right Face
-            (Vector3::FORWARD, Vector3::DOWN, Vector3::RIGHT), // Bottom Front Right Face
-            (Vector3::DOWN, Vector3::FORWARD, Vector3::LEFT), // Bottom Front Left Face
-            (Vector3::BACK, Vector3::DOWN, Vector3::LEFT),   // Bottom Back Left Face
-            (Vector3::DOWN, Vector3::BACK, Vector3::RIGHT),  // Bottom Back Right Face
-        ];
-        for particle in &self.particles {
-            if particle.interior {
-                continue;
-            }
-            let material_color = match particle.material {
-                ParticleMaterial::Flesh => Color::from_rgb(1.0, 0.3, 0.3),
-                ParticleMaterial::Skin => Color::from_rgb(1.0, 0.5, 0.7),
-                ParticleMaterial::Bone => Color::from_rgb(1.0, 1.0, 1.0),
-                ParticleMaterial::Heart => Color::from_rgb(1.0, 0.0, 0.0),
-                ParticleMaterial::Wing => Color::from_rgb(0.3, 0.3, 1.0),
-            };
-            let position = transform_inv * particle.position;
-            render_geometry.surface_set_color(material_color);
-            for (pos1, pos2, pos3) in faces.clone() {
-                render_geometry.surface_set_normal((pos1 + pos2 + pos3).normalized());
-                render_geometry.surface_add_vertex(position + pos1 * diamond_size);
-                render_geometry.surface_add_vertex(position + pos2 * diamond_size);
-                render_geometry.surface_add_vertex(position + pos3 * diamond_size);
+        // Render particles, as a skin, as instanced spheres or as immediate debug diamonds
+        let use_instances = !self.render_surface && self.use_multimesh;
+        if let Some(particle_instances) = &mut self.particle_instances {
+            particle_instances.set_visible(use_instances);
+        }
+        if use_instances {
+            self.update_particle_instances(transform_inv, alpha);
+        } else if self.render_surface {
+            render_geometry.call(
+                StringName::from("surface_begin"),
+                &[
+                    Variant::from(PrimitiveType::PRIMITIVE_TRIANGLES),
+                    Variant::from(material.clone()),
+                ],
+            );
+            let positions: Vec<Vector3> = self
+                .particles
+                .iter()
+                .map(|particle| transform_inv * particle.render_position(alpha))
+                .collect();
+            let colors: Vec<Color> = self.particles.iter().map(Particle::color).collect();
+            for vertex in extract_surface(&positions, &colors) {
+                render_geometry.surface_set_color(vertex.color);
+                render_geometry.surface_set_normal(vertex.normal);
+                render_geometry.surface_add_vertex(vertex.position);
             }
+            render_geometry.surface_end();
+        } else {
+            render_geometry.call(
+                StringName::from("surface_begin"),
+                &[
+                    Variant::from(PrimitiveType::PRIMITIVE_TRIANGLES),
+                    Variant::from(material.clone()),
+                ],
+            );
+            self.render_diamonds(&mut render_geometry, transform_inv, alpha);
+            render_geometry.surface_end();
         }
-        render_geometry.surface_end();
 
         // Render cut plane on camera
         let cut_plane = match self.get_cut_plane() {
@@ -79,6 +80,11 @@ impl Entity {
         };
         render_cut_plane(&mut render_geometry, material.clone(), cut_plane);
 
+        // Connection lines belong to the debug diamonds, the other paths skip rebuilding them
+        if self.render_surface || use_instances {
+            return;
+        }
+
         // Render connection lines
         render_geometry.call(
             StringName::from("surface_begin"),
@@ -90,8 +96,9 @@ impl Entity {
         for particle in &self.particles {
             for connection in &particle.connections {
                 if connection.active {
-                    let a = transform_inv * particle.position;
-                    let b = transform_inv * self.particles[connection.target_index].position;
+                    let a = transform_inv * particle.render_position(alpha);
+                    let b = transform_inv
+                        * self.particles[connection.target_index].render_position(alpha);
                     let dist = a.distance_to(b);
                     let strain = f32::abs(connection.distance - dist);
 
@@ -111,15 +118,134 @@ impl Entity {
         render_geometry.surface_end();
     }
 
-    fn get_immediate_mesh(&self) -> Result<Gd<ImmediateMesh>> {
+    fn render_diamonds(
+        &self,
+        render_geometry: &mut ImmediateMesh,
+        transform_inv: Transform3D,
+        alpha: f32,
+    ) {
+        let diamond_size = PARTICLE_MARKER_SIZE;
+        let faces = vec![
+            (Vector3::FORWARD, Vector3::RIGHT, Vector3::UP), // Top Front Right Face
+            (Vector3::UP, Vector3::LEFT, Vector3::FORWARD),  // Top Front Left Face
+            (Vector3::BACK, Vector3::LEFT, Vector3::UP),     // Top Back Left Face
+            (Vector3::UP, Vector3::RIGHT, Vector3::BACK),    // Top Back Right Face
+            (Vector3::FORWARD, Vector3::DOWN, Vector3::RIGHT), // Bottom Front Right Face
+            (Vector3::DOWN, Vector3::FORWARD, Vector3::LEFT), // Bottom Front Left Face
+            (Vector3::BACK, Vector3::DOWN, Vector3::LEFT),   // Bottom Back Left Face
+            (Vector3::DOWN, Vector3::BACK, Vector3::RIGHT),  // Bottom Back Right Face
+        ];
+        for particle in &self.particles {
+            if particle.interior {
+                continue;
+            }
+            let position = transform_inv * particle.render_position(alpha);
+            render_geometry.surface_set_color(particle.color());
+            for (pos1, pos2, pos3) in faces.clone() {
+                render_geometry.surface_set_normal((pos1 + pos2 + pos3).normalized());
+                render_geometry.surface_add_vertex(position + pos1 * diamond_size);
+                render_geometry.surface_add_vertex(position + pos2 * diamond_size);
+                render_geometry.surface_add_vertex(position + pos3 * diamond_size);
+            }
+        }
+    }
+
+    pub fn add_render_meshes(&mut self) {
+        let mut render_mesh = MeshInstance3D::new_alloc();
+        render_mesh.set_mesh(ImmediateMesh::new().upcast::<Mesh>());
+        self.base.add_child(render_mesh.clone().upcast::<Node>());
+        self.render_mesh = Some(render_mesh);
+
+        // Instances start empty and are filled in when rendering
+        let mut sphere = SphereMesh::new();
+        sphere.set_radius(PARTICLE_MARKER_SIZE);
+        sphere.set_height(PARTICLE_MARKER_SIZE * 2.0);
+        sphere.set_radial_segments(8);
+        sphere.set_rings(4);
+        sphere.set_material(load_debug_material());
+        let mut multimesh = MultiMesh::new();
+        multimesh.set_transform_format(TransformFormat::TRANSFORM_3D);
+        multimesh.set_use_colors(true);
+        multimesh.set_mesh(sphere.upcast::<Mesh>());
+        let mut particle_instances = MultiMeshInstance3D::new_alloc();
+        particle_instances.set_multimesh(multimesh);
         self.base
-            .get_child(self.base.get_child_count() - 1)
-            .context("No child at index 0")?
-            .try_cast::<MeshInstance3D>()
-            .context("Failed to cast node to MeshInstance3D")?
+            .add_child(particle_instances.clone().upcast::<Node>());
+        self.particle_instances = Some(particle_instances);
+    }
+
+    // Write every particle's transform and color into the multimesh buffer in one go
+    fn update_particle_instances(&mut self, transform_inv: Transform3D, alpha: f32) {
+        let Some(mut multimesh) = self
+            .particle_instances
+            .as_ref()
+            .and_then(|particle_instances| particle_instances.get_multimesh())
+        else {
+            return;
+        };
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let instance_count = self.particles.len() as i32;
+        if multimesh.get_instance_count() != instance_count {
+            multimesh.set_instance_count(instance_count);
+        }
+
+        // Each instance is a 3x4 row major transform followed by a color
+        let mut buffer = Vec::with_capacity(self.particles.len() * 16);
+        for particle in &self.particles {
+            let position = transform_inv * particle.render_position(alpha);
+            // Interior particles are collapsed to nothing rather than removed
+            let scale = if particle.interior { 0.0 } else { 1.0 };
+            let color = particle.color();
+            buffer.extend_from_slice(&[
+                scale, 0.0, 0.0, position.x, //
+                0.0, scale, 0.0, position.y, //
+                0.0, 0.0, scale, position.z, //
+                color.r, color.g, color.b, color.a,
+            ]);
+        }
+        multimesh.set_buffer(PackedFloat32Array::from(buffer.as_slice()));
+    }
+
+    fn get_immediate_mesh(&self) -> Result<Gd<ImmediateMesh>> {
+        self.render_mesh
+            .as_ref()
+            .context("Render mesh has not been created")?
             .get_mesh()
             .context("MeshInstance3D does not have a mesh")?
             .try_cast::<ImmediateMesh>()
             .context("Failed to cast mesh to ImmediateMesh")
     }
 }
+
+fn load_debug_material() -> Gd<Material> {
+    ResourceLoader::singleton()
+        .load(GodotString::from_str("res://debug_node.tres").unwrap())
+        .unwrap()
+        .cast::<Material>()
+}
+
+impl Particle {
+    // Material color, darkened by any bruising
+    pub fn color(&self) -> Color {
+        let color = self.material.color();
+        let brightness = 1.0 - self.bruise * 0.8;
+        Color::from_rgb(
+            color.r * brightness,
+            color.g * brightness,
+            color.b * brightness,
+        )
+    }
+}
+
+impl ParticleMaterial {
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Flesh => Color::from_rgb(1.0, 0.3, 0.3),
+            Self::Skin => Color::from_rgb(1.0, 0.5, 0.7),
+            Self::Bone => Color::from_rgb(1.0, 1.0, 1.0),
+            Self::Heart => Color::from_rgb(1.0, 0.0, 0.0),
+            Self::Wing => Color::from_rgb(0.3, 0.3, 1.0),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use super::ParticleMaterial;
+use godot::{
+    engine::{CapsuleMesh, CylinderMesh, Mesh},
+    prelude::*,
+};
+
+pub enum ShapeType {
+    Box,
+    Ellipsoid,
+    Capsule {
+        radius: f32,
+        height: f32,
+    },
+    Cylinder {
+        top_radius: f32,
+        bottom_radius: f32,
+        height: f32,
+    },
+    // Any other mesh, voxelized from its triangles
+    Mesh {
+        faces: Vec<Vector3>,
+        bounds_min: Vector3,
+        bounds_max: Vector3,
+    },
+}
+
+impl ShapeType {
+    pub fn from_mesh(mesh: Gd<Mesh>) -> Option<Self> {
+        match mesh.get_class().to_string().as_str() {
+            "BoxMesh" => Some(Self::Box),
+            "SphereMesh" => Some(Self::Ellipsoid),
+            "CapsuleMesh" => {
+                let capsule = mesh.cast::<CapsuleMesh>();
+                Some(Self::Capsule {
+                    radius: capsule.get_radius(),
+                    height: capsule.get_height(),
+                })
+            }
+            "CylinderMesh" => {
+                let cylinder = mesh.cast::<CylinderMesh>();
+                Some(Self::Cylinder {
+                    top_radius: cylinder.get_top_radius(),
+                    bottom_radius: cylinder.get_bottom_radius(),
+                    height: cylinder.get_height(),
+                })
+            }
+            _ => {
+                let faces = mesh.get_faces().to_vec();
+                if faces.len() < 3 {
+                    return None;
+                }
+                let mut bounds_min = faces[0];
+                let mut bounds_max = faces[0];
+                for face in &faces {
+                    bounds_min = component_min(bounds_min, *face);
+                    bounds_max = component_max(bounds_max, *face);
+                }
+                Some(Self::Mesh {
+                    faces,
+                    bounds_min,
+                    bounds_max,
+                })
+            }
+        }
+    }
+}
+
+pub struct Shape {
+    pub name: String,
+    pub material: ParticleMaterial,
+    pub transform: Transform3D,
+    pub shape_type: ShapeType,
+}
+
+impl Shape {
+    // Corners of the shape's bounding box, in world space
+    pub fn corners(&self) -> [Vector3; 8] {
+        let (min, max) = self.local_bounds();
+        [
+            Vector3::new(min.x, min.y, min.z),
+            Vector3::new(max.x, min.y, min.z),
+            Vector3::new(min.x, max.y, min.z),
+            Vector3::new(max.x, max.y, min.z),
+            Vector3::new(min.x, min.y, max.z),
+            Vector3::new(max.x, min.y, max.z),
+            Vector3::new(min.x, max.y, max.z),
+            Vector3::new(max.x, max.y, max.z),
+        ]
+        .map(|corner| self.transform * corner)
+    }
+
+    fn local_bounds(&self) -> (Vector3, Vector3) {
+        match &self.shape_type {
+            ShapeType::Box | ShapeType::Ellipsoid => {
+                (Vector3::new(-0.5, -0.5, -0.5), Vector3::new(0.5, 0.5, 0.5))
+            }
+            ShapeType::Capsule { radius, height } => {
+                let half_height = f32::max(height * 0.5, *radius);
+                (
+                    Vector3::new(-radius, -half_height, -radius),
+                    Vector3::new(*radius, half_height, *radius),
+                )
+            }
+            ShapeType::Cylinder {
+                top_radius,
+                bottom_radius,
+                height,
+            } => {
+                let radius = f32::max(*top_radius, *bottom_radius);
+                (
+                    Vector3::new(-radius, -height * 0.5, -radius),
+                    Vector3::new(radius, height * 0.5, radius),
+                )
+            }
+            ShapeType::Mesh {
+                bounds_min,
+                bounds_max,
+                ..
+            } => (*bounds_min, *bounds_max),
+        }
+    }
+
+    // Whether the global position is inside the shape, and its distance to the shape's surface
+    pub fn sample(&self, position: Vector3) -> (bool, f32) {
+        let local_position = self.transform.affine_inverse() * position;
+
+        match &self.shape_type {
+            ShapeType::Box => {
+                let dx = 0.5 - local_position.x.abs();
+                let dy = 0.5 - local_position.y.abs();
+                let dz = 0.5 - local_position.z.abs();
+                let distance = dx.min(dy).min(dz);
+                (distance > 0.0, distance)
+            }
+            ShapeType::Ellipsoid => {
+                let norm_squared =
+                    local_position.x.powi(2) + local_position.y.powi(2) + local_position.z.powi(2);
+                let distance = 0.5 - norm_squared.sqrt();
+                (norm_squared < 0.25, distance)
+            }
+            ShapeType::Capsule { radius, height } => {
+                // Distance to the segment between the centers of the two caps
+                let half_segment = f32::max(height * 0.5 - radius, 0.0);
+                let closest_y = local_position.y.clamp(-half_segment, half_segment);
+                let distance =
+                    radius - local_position.distance_to(Vector3::new(0.0, closest_y, 0.0));
+                (distance > 0.0, distance)
+            }
+            ShapeType::Cylinder {
+                top_radius,
+                bottom_radius,
+                height,
+            } => {
+                // Radius tapers linearly from the bottom to the top
+                let t = (local_position.y / height + 0.5).clamp(0.0, 1.0);
+                let radius = bottom_radius + (top_radius - bottom_radius) * t;
+                let radial = Vector2::new(local_position.x, local_position.z).length();
+                let distance = f32::min(radius - radial, height * 0.5 - local_position.y.abs());
+                (distance > 0.0, distance)
+            }
+            ShapeType::Mesh { faces, .. } => {
+                if !mesh_contains(faces, local_position) {
+                    return (false, 0.0);
+                }
+                let distance = faces
+                    .chunks_exact(3)
+                    .map(|triangle| {
+                        local_position.distance_to(closest_point_on_triangle(
+                            local_position,
+                            triangle[0],
+                            triangle[1],
+                            triangle[2],
+                        ))
+                    })
+                    .fold(f32::MAX, f32::min);
+                (true, distance)
+            }
+        }
+    }
+}
+
+// Counts crossings of a ray against the triangles, odd means inside a closed mesh
+fn mesh_contains(faces: &[Vector3], point: Vector3) -> bool {
+    // Skewed so the ray is unlikely to graze edges of axis aligned geometry
+    let direction = Vector3::new(1.0, 0.013_7, 0.007_1).normalized();
+    let crossings = faces
+        .chunks_exact(3)
+        .filter(|triangle| {
+            ray_hits_triangle(point, direction, triangle[0], triangle[1], triangle[2])
+        })
+        .count();
+    crossings % 2 == 1
+}
+
+// Möller-Trumbore intersection
+fn ray_hits_triangle(
+    origin: Vector3,
+    direction: Vector3,
+    a: Vector3,
+    b: Vector3,
+    c: Vector3,
+) -> bool {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+    let p = direction.cross(edge_2);
+    let determinant = edge_1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return false;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let to_origin = origin - a;
+    let u = to_origin.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+    let q = to_origin.cross(edge_1);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    edge_2.dot(q) * inverse_determinant > 0.0
+}
+
+// From Real-Time Collision Detection, by region of the triangle the point projects onto
+fn closest_point_on_triangle(point: Vector3, a: Vector3, b: Vector3, c: Vector3) -> Vector3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+fn component_min(a: Vector3, b: Vector3) -> Vector3 {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn component_max(a: Vector3, b: Vector3) -> Vector3 {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
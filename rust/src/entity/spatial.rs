@@ -0,0 +1,103 @@
+use godot::prelude::*;
+use std::collections::HashMap;
+
+type CellKey = (isize, isize, isize);
+
+// Buckets particle indices into cubic cells for fast neighborhood lookups
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<CellKey, Vec<usize>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn from_positions(cell_size: f32, positions: impl Iterator<Item = Vector3>) -> Self {
+        let mut hash = Self::new(cell_size);
+        for (index, position) in positions.enumerate() {
+            hash.insert(index, position);
+        }
+        hash
+    }
+
+    pub fn insert(&mut self, index: usize, position: Vector3) {
+        let key = self.cell(position);
+        self.cells.entry(key).or_default().push(index);
+    }
+
+    // Floor rather than truncate, so cells either side of zero are the same size
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn cell(&self, position: Vector3) -> CellKey {
+        (
+            (position.x / self.cell_size).floor() as isize,
+            (position.y / self.cell_size).floor() as isize,
+            (position.z / self.cell_size).floor() as isize,
+        )
+    }
+
+    // All indices in the 3x3x3 block of cells around the position, a superset of those within cell_size
+    pub fn neighbors(&self, position: Vector3) -> impl Iterator<Item = usize> + '_ {
+        let (cell_x, cell_y, cell_z) = self.cell(position);
+        (-1..=1)
+            .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (dx, dy, dz))))
+            .filter_map(move |(dx, dy, dz)| {
+                self.cells.get(&(cell_x + dx, cell_y + dy, cell_z + dz))
+            })
+            .flat_map(|indices| indices.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_neighbors(hash: &SpatialHash, position: Vector3) -> Vec<usize> {
+        let mut neighbors: Vec<usize> = hash.neighbors(position).collect();
+        neighbors.sort_unstable();
+        neighbors
+    }
+
+    #[test]
+    fn finds_points_within_cell_size() {
+        let positions = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.09, 0.0, 0.0),
+            Vector3::new(0.0, -0.09, 0.05),
+            Vector3::new(-0.05, 0.05, -0.09),
+        ];
+        let hash = SpatialHash::from_positions(0.1, positions.iter().copied());
+        assert_eq!(sorted_neighbors(&hash, positions[0]), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn separates_vertical_columns() {
+        let positions = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 0.0, 0.15),
+        ];
+        let hash = SpatialHash::from_positions(0.1, positions.iter().copied());
+        assert_eq!(sorted_neighbors(&hash, positions[0]), vec![0, 3]);
+        assert_eq!(sorted_neighbors(&hash, positions[1]), vec![1]);
+    }
+
+    #[test]
+    fn cells_are_uniform_across_zero() {
+        let hash = SpatialHash::new(0.1);
+        assert_eq!(hash.cell(Vector3::new(0.05, 0.05, 0.05)), (0, 0, 0));
+        assert_eq!(hash.cell(Vector3::new(-0.05, -0.05, -0.05)), (-1, -1, -1));
+        assert_eq!(hash.cell(Vector3::new(-0.15, 0.25, 0.0)), (-2, 2, 0));
+    }
+
+    #[test]
+    fn empty_hash_has_no_neighbors() {
+        let hash = SpatialHash::new(0.1);
+        assert_eq!(hash.neighbors(Vector3::ZERO).count(), 0);
+    }
+}
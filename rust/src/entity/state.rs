@@ -0,0 +1,221 @@
+use super::{bones, islands, volume, Connection, Entity, Particle, ParticleMaterial};
+use anyhow::{bail, Context, Result};
+use godot::{
+    engine::{file_access::ModeFlags, FileAccess},
+    prelude::*,
+};
+
+const MAGIC: &[u8; 4] = b"SKYE";
+const VERSION: u32 = 1;
+
+const FLAG_INTERIOR: u8 = 1;
+const FLAG_ANCHORED: u8 = 1 << 1;
+const FLAG_ALIVE: u8 = 1 << 2;
+
+const ENTITY_FLAG_ALIVE: u8 = 1;
+const ENTITY_FLAG_HEART_BEATING: u8 = 1 << 1;
+
+impl Entity {
+    // Write the particles, their materials and connections to a little endian binary file,
+    // along with whether the entity is alive so a dead body stays dead
+    pub fn write_state(&self, path: &GodotString) -> Result<()> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        push_u32(&mut bytes, VERSION);
+        let mut entity_flags = 0;
+        if self.alive {
+            entity_flags |= ENTITY_FLAG_ALIVE;
+        }
+        if self.heart_beating {
+            entity_flags |= ENTITY_FLAG_HEART_BEATING;
+        }
+        bytes.push(entity_flags);
+        push_f32(&mut bytes, self.heart_phase);
+        push_u32(&mut bytes, to_u32(self.central_particle)?);
+        push_u32(&mut bytes, to_u32(self.particles.len())?);
+        for particle in &self.particles {
+            push_vector(&mut bytes, particle.position);
+            push_vector(&mut bytes, particle.old_position);
+            bytes.push(material_to_byte(&particle.material));
+            let mut flags = 0;
+            if particle.interior {
+                flags |= FLAG_INTERIOR;
+            }
+            if particle.anchored {
+                flags |= FLAG_ANCHORED;
+            }
+            if particle.alive {
+                flags |= FLAG_ALIVE;
+            }
+            bytes.push(flags);
+            push_f32(&mut bytes, particle.damage);
+            push_f32(&mut bytes, particle.bruise);
+            push_u32(&mut bytes, to_u32(particle.connections.len())?);
+            for connection in &particle.connections {
+                push_u32(&mut bytes, to_u32(connection.target_index)?);
+                push_f32(&mut bytes, connection.distance);
+                bytes.push(u8::from(connection.active));
+            }
+        }
+
+        let mut file = FileAccess::open(path.clone(), ModeFlags::WRITE)
+            .with_context(|| format!("Failed to open {path} for writing"))?;
+        file.store_buffer(PackedByteArray::from(bytes.as_slice()));
+        file.close();
+        godot_print!("Saved {} particles to {}", self.particles.len(), path);
+        Ok(())
+    }
+
+    // Replace the particles with those from write_state, rebuilding everything derived from them
+    pub fn read_state(&mut self, path: &GodotString) -> Result<()> {
+        let mut file = FileAccess::open(path.clone(), ModeFlags::READ)
+            .with_context(|| format!("Failed to open {path} for reading"))?;
+        #[allow(clippy::cast_possible_wrap)]
+        let length = file.get_length() as i64;
+        let bytes = file.get_buffer(length).to_vec();
+        file.close();
+
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+        if reader.take(MAGIC.len())? != MAGIC {
+            bail!("{path} is not an entity state file");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            bail!("Unsupported entity state version {version}");
+        }
+        let entity_flags = reader.u8()?;
+        let heart_phase = reader.f32()?;
+        let central_particle = reader.usize()?;
+        let particle_count = reader.usize()?;
+
+        // A corrupt count shouldn't reserve more than the file could hold
+        let mut particles = Vec::with_capacity(particle_count.min(bytes.len()));
+        for _ in 0..particle_count {
+            let position = reader.vector()?;
+            let old_position = reader.vector()?;
+            let material = material_from_byte(reader.u8()?)?;
+            let flags = reader.u8()?;
+            let mut particle = Particle::new(position, material, flags & FLAG_INTERIOR != 0);
+            particle.old_position = old_position;
+            particle.anchored = flags & FLAG_ANCHORED != 0;
+            particle.alive = flags & FLAG_ALIVE != 0;
+            particle.damage = reader.f32()?;
+            particle.bruise = reader.f32()?;
+            let connection_count = reader.usize()?;
+            for _ in 0..connection_count {
+                let target_index = reader.usize()?;
+                let mut connection = Connection::new(target_index, reader.f32()?);
+                connection.active = reader.u8()? != 0;
+                particle.connections.push(connection);
+            }
+            particles.push(particle);
+        }
+
+        // Validate indices before anything relies on them
+        if central_particle >= particles.len() && !particles.is_empty() {
+            bail!("Central particle {central_particle} is out of range");
+        }
+        for particle in &particles {
+            for connection in &particle.connections {
+                if connection.target_index >= particles.len() {
+                    bail!(
+                        "Connection target {} is out of range",
+                        connection.target_index
+                    );
+                }
+            }
+        }
+
+        self.particles = particles;
+        self.central_particle = central_particle;
+        self.alive = entity_flags & ENTITY_FLAG_ALIVE != 0;
+        self.heart_beating = entity_flags & ENTITY_FLAG_HEART_BEATING != 0;
+        self.heart_phase = heart_phase;
+        self.volume_clusters = volume::build_volume_clusters(&self.particles);
+        self.bone_groups = bones::build_bone_groups(&self.particles);
+        self.islands = islands::IslandTracker::new(&self.particles);
+        self.mark_topology_dirty();
+        self.drag = None;
+        self.accumulator = 0.0;
+        godot_print!("Loaded {} particles from {}", self.particles.len(), path);
+        Ok(())
+    }
+}
+
+const fn material_to_byte(material: &ParticleMaterial) -> u8 {
+    match material {
+        ParticleMaterial::Flesh => 0,
+        ParticleMaterial::Skin => 1,
+        ParticleMaterial::Bone => 2,
+        ParticleMaterial::Heart => 3,
+        ParticleMaterial::Wing => 4,
+    }
+}
+
+fn material_from_byte(byte: u8) -> Result<ParticleMaterial> {
+    Ok(match byte {
+        0 => ParticleMaterial::Flesh,
+        1 => ParticleMaterial::Skin,
+        2 => ParticleMaterial::Bone,
+        3 => ParticleMaterial::Heart,
+        4 => ParticleMaterial::Wing,
+        _ => bail!("Unknown particle material {byte}"),
+    })
+}
+
+fn to_u32(value: usize) -> Result<u32> {
+    u32::try_from(value).context("Too many particles to save")
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_f32(bytes: &mut Vec<u8>, value: f32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_vector(bytes: &mut Vec<u8>, value: Vector3) {
+    push_f32(bytes, value.x);
+    push_f32(bytes, value.y);
+    push_f32(bytes, value.z);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let slice = self
+            .bytes
+            .get(self.offset..self.offset + count)
+            .context("State file is truncated")?;
+        self.offset += count;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        Ok(usize::try_from(self.u32()?)?)
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn vector(&mut self) -> Result<Vector3> {
+        Ok(Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
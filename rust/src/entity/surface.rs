@@ -0,0 +1,253 @@
+use super::PARTICLE_DISTANCE;
+use godot::prelude::*;
+
+// Radius each particle contributes density over, wide enough to bridge neighbors
+const KERNEL_RADIUS: f32 = PARTICLE_DISTANCE * 1.5;
+const GRID_SPACING: f32 = PARTICLE_DISTANCE * 0.5;
+// Density at which the surface sits, a lone particle reaches 1 at its center
+const ISO_LEVEL: f32 = 0.5;
+
+pub struct SurfaceVertex {
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub color: Color,
+}
+
+// Density and the strongest contributing particle, sampled at grid corners
+struct DensityGrid {
+    origin: Vector3,
+    dims: (usize, usize, usize),
+    density: Vec<f32>,
+    strongest: Vec<Option<(f32, usize)>>,
+}
+
+impl DensityGrid {
+    const fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.dims.0 * (y + self.dims.1 * z)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn corner_position(&self, x: usize, y: usize, z: usize) -> Vector3 {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32) * GRID_SPACING
+    }
+
+    fn inside(&self, x: usize, y: usize, z: usize) -> bool {
+        self.density[self.index(x, y, z)] >= ISO_LEVEL
+    }
+
+    // Central differences, pointing from dense to sparse so it faces outwards
+    fn normal(&self, x: usize, y: usize, z: usize) -> Vector3 {
+        let sample = |x: usize, y: usize, z: usize| {
+            let clamp = |value: usize, max: usize| value.min(max - 1);
+            self.density[self.index(
+                clamp(x, self.dims.0),
+                clamp(y, self.dims.1),
+                clamp(z, self.dims.2),
+            )]
+        };
+        let gradient = Vector3::new(
+            sample(x + 1, y, z) - sample(x.saturating_sub(1), y, z),
+            sample(x, y + 1, z) - sample(x, y.saturating_sub(1), z),
+            sample(x, y, z + 1) - sample(x, y, z.saturating_sub(1)),
+        );
+        -gradient.normalized()
+    }
+}
+
+fn kernel(distance_squared: f32) -> f32 {
+    let falloff = 1.0 - distance_squared / (KERNEL_RADIUS * KERNEL_RADIUS);
+    if falloff > 0.0 {
+        falloff * falloff
+    } else {
+        0.0
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn build_density_grid(positions: &[Vector3]) -> DensityGrid {
+    let mut bounds_min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+    let mut bounds_max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+    for position in positions {
+        bounds_min = Vector3::new(
+            bounds_min.x.min(position.x),
+            bounds_min.y.min(position.y),
+            bounds_min.z.min(position.z),
+        );
+        bounds_max = Vector3::new(
+            bounds_max.x.max(position.x),
+            bounds_max.y.max(position.y),
+            bounds_max.z.max(position.z),
+        );
+    }
+    // Pad so the outermost corners are always empty and the surface closes
+    let padding = Vector3::ONE * (KERNEL_RADIUS + GRID_SPACING * 2.0);
+    let origin = bounds_min - padding;
+    let size = bounds_max + padding - origin;
+    let dims = (
+        (size.x / GRID_SPACING).ceil() as usize + 1,
+        (size.y / GRID_SPACING).ceil() as usize + 1,
+        (size.z / GRID_SPACING).ceil() as usize + 1,
+    );
+
+    let mut grid = DensityGrid {
+        origin,
+        dims,
+        density: vec![0.0; dims.0 * dims.1 * dims.2],
+        strongest: vec![None; dims.0 * dims.1 * dims.2],
+    };
+
+    // Splat each particle onto the corners within its kernel radius
+    let reach = (KERNEL_RADIUS / GRID_SPACING).ceil() as isize;
+    for (particle_index, &position) in positions.iter().enumerate() {
+        let local = (position - origin) / GRID_SPACING;
+        let (cx, cy, cz) = (
+            local.x.round() as isize,
+            local.y.round() as isize,
+            local.z.round() as isize,
+        );
+        for z in (cz - reach).max(0)..=(cz + reach).min(dims.2 as isize - 1) {
+            for y in (cy - reach).max(0)..=(cy + reach).min(dims.1 as isize - 1) {
+                for x in (cx - reach).max(0)..=(cx + reach).min(dims.0 as isize - 1) {
+                    let (x, y, z) = (x as usize, y as usize, z as usize);
+                    let corner = grid.corner_position(x, y, z);
+                    let weight = kernel(corner.distance_squared_to(position));
+                    if weight <= 0.0 {
+                        continue;
+                    }
+                    let index = grid.index(x, y, z);
+                    grid.density[index] += weight;
+                    let is_strongest = match grid.strongest[index] {
+                        Some((strongest, _)) => weight > strongest,
+                        None => true,
+                    };
+                    if is_strongest {
+                        grid.strongest[index] = Some((weight, particle_index));
+                    }
+                }
+            }
+        }
+    }
+    grid
+}
+
+// Extract a triangle list enclosing the particles using surface nets
+pub fn extract_surface(positions: &[Vector3], colors: &[Color]) -> Vec<SurfaceVertex> {
+    if positions.is_empty() {
+        return Vec::new();
+    }
+    let grid = build_density_grid(positions);
+    let (nx, ny, nz) = grid.dims;
+    let cell_index = |x: usize, y: usize, z: usize| x + (nx - 1) * (y + (ny - 1) * z);
+
+    // Place one vertex per cell that the surface passes through
+    let corner_offsets = [
+        (0, 0, 0),
+        (1, 0, 0),
+        (0, 1, 0),
+        (1, 1, 0),
+        (0, 0, 1),
+        (1, 0, 1),
+        (0, 1, 1),
+        (1, 1, 1),
+    ];
+    let cube_edges = [
+        (0, 1),
+        (2, 3),
+        (4, 5),
+        (6, 7),
+        (0, 2),
+        (1, 3),
+        (4, 6),
+        (5, 7),
+        (0, 4),
+        (1, 5),
+        (2, 6),
+        (3, 7),
+    ];
+    let mut cell_vertices: Vec<Option<(Vector3, Vector3, Color)>> =
+        vec![None; (nx - 1) * (ny - 1) * (nz - 1)];
+    for z in 0..nz - 1 {
+        for y in 0..ny - 1 {
+            for x in 0..nx - 1 {
+                let corners = corner_offsets.map(|(dx, dy, dz)| (x + dx, y + dy, z + dz));
+                let densities = corners.map(|(cx, cy, cz)| grid.density[grid.index(cx, cy, cz)]);
+                let inside_count = densities.iter().filter(|&&d| d >= ISO_LEVEL).count();
+                if inside_count == 0 || inside_count == 8 {
+                    continue;
+                }
+
+                // Average where the surface crosses the cube's edges
+                let mut sum = Vector3::ZERO;
+                let mut crossings = 0.0;
+                for (a, b) in cube_edges {
+                    if (densities[a] >= ISO_LEVEL) == (densities[b] >= ISO_LEVEL) {
+                        continue;
+                    }
+                    let t = (ISO_LEVEL - densities[a]) / (densities[b] - densities[a]);
+                    let (ax, ay, az) = corners[a];
+                    let (bx, by, bz) = corners[b];
+                    sum += grid
+                        .corner_position(ax, ay, az)
+                        .lerp(grid.corner_position(bx, by, bz), t);
+                    crossings += 1.0;
+                }
+                let position = sum / crossings;
+
+                // Color by the particle dominating the densest corner
+                let densest = (0..8)
+                    .max_by(|&a, &b| densities[a].total_cmp(&densities[b]))
+                    .unwrap_or(0);
+                let (cx, cy, cz) = corners[densest];
+                let color = grid.strongest[grid.index(cx, cy, cz)]
+                    .map_or(Color::WHITE, |(_, particle)| colors[particle]);
+
+                let normal = grid.normal(cx, cy, cz);
+                cell_vertices[cell_index(x, y, z)] = Some((position, normal, color));
+            }
+        }
+    }
+
+    // Join the vertices of the four cells around every grid edge the surface crosses
+    let mut triangles = Vec::new();
+    for z in 1..nz - 1 {
+        for y in 1..ny - 1 {
+            for x in 1..nx - 1 {
+                let inside = grid.inside(x, y, z);
+                // Edges along +x, +y and +z, with the cells sharing each edge
+                let edges = [
+                    (
+                        (x + 1, y, z),
+                        [(x, y - 1, z - 1), (x, y, z - 1), (x, y, z), (x, y - 1, z)],
+                    ),
+                    (
+                        (x, y + 1, z),
+                        [(x - 1, y, z - 1), (x - 1, y, z), (x, y, z), (x, y, z - 1)],
+                    ),
+                    (
+                        (x, y, z + 1),
+                        [(x - 1, y - 1, z), (x, y - 1, z), (x, y, z), (x - 1, y, z)],
+                    ),
+                ];
+                for ((ex, ey, ez), cells) in edges {
+                    if ex >= nx || ey >= ny || ez >= nz || grid.inside(ex, ey, ez) == inside {
+                        continue;
+                    }
+                    let quad = cells.map(|(cx, cy, cz)| &cell_vertices[cell_index(cx, cy, cz)]);
+                    let [Some(a), Some(b), Some(c), Some(d)] = quad else {
+                        continue;
+                    };
+                    // Keep a consistent winding relative to which side is inside
+                    let (b, d) = if inside { (b, d) } else { (d, b) };
+                    for vertex in [a, b, c, a, c, d] {
+                        triangles.push(SurfaceVertex {
+                            position: vertex.0,
+                            normal: vertex.1,
+                            color: vertex.2,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    triangles
+}
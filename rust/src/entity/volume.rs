@@ -0,0 +1,115 @@
+use super::{materials::PropertiesTable, neighbor_links, Particle};
+use godot::prelude::*;
+
+// Fewest particles that can enclose a volume
+const MIN_CLUSTER_SIZE: usize = 4;
+
+// A particle and everything connected to it, kept at its rest spread so the region can't collapse
+pub struct VolumeCluster {
+    members: Vec<usize>,
+    // Connections holding the cluster together as (particle index, connection index)
+    links: Vec<(usize, usize)>,
+    rest_radius: f32,
+}
+
+impl VolumeCluster {
+    // A cluster is only valid while all its connections are intact
+    pub fn is_active(&self, particles: &[Particle]) -> bool {
+        self.links
+            .iter()
+            .all(|&(particle, connection)| particles[particle].connections[connection].active)
+    }
+
+    pub fn compliance(&self, particles: &[Particle], properties: &PropertiesTable) -> f32 {
+        let total: f32 = self
+            .members
+            .iter()
+            .map(|&i| properties.get(&particles[i].material).volume_compliance)
+            .sum();
+        #[allow(clippy::cast_precision_loss)]
+        let count = self.members.len() as f32;
+        total / count
+    }
+}
+
+pub fn build_volume_clusters(particles: &[Particle]) -> Vec<VolumeCluster> {
+    // Every connection touching each particle, active or not, as the clusters track their state
+    let links = neighbor_links(particles, |_, _| true);
+
+    let mut clusters = Vec::new();
+    for (center, center_links) in links.into_iter().enumerate() {
+        if center_links.len() + 1 < MIN_CLUSTER_SIZE {
+            continue;
+        }
+        let mut members = vec![center];
+        members.extend(center_links.iter().map(|link| link.neighbor));
+        let positions: Vec<Vector3> = members.iter().map(|&i| particles[i].position).collect();
+        let rest_radius = mean_radius(&positions);
+
+        clusters.push(VolumeCluster {
+            members,
+            links: center_links
+                .into_iter()
+                .map(|link| (link.owner, link.connection))
+                .collect(),
+            rest_radius,
+        });
+    }
+    clusters
+}
+
+// XPBD constraint on the mean distance of the members from their centroid
+pub fn solve_volume_cluster(
+    cluster: &VolumeCluster,
+    positions: &mut [Vector3],
+    inverse_masses: &[f32],
+    compliance: f32,
+    lambda: &mut f32,
+    delta: f32,
+) {
+    let cluster_positions: Vec<Vector3> = cluster.members.iter().map(|&i| positions[i]).collect();
+    #[allow(clippy::cast_precision_loss)]
+    let count = cluster_positions.len() as f32;
+    let centroid = centroid(&cluster_positions);
+
+    let constraint = mean_radius(&cluster_positions) - cluster.rest_radius;
+
+    // Each member's gradient is its outward direction over the member count
+    let weight_sum: f32 = cluster.members.iter().map(|&i| inverse_masses[i]).sum();
+    let alpha = compliance / (delta * delta);
+    let denominator = weight_sum / (count * count) + alpha;
+    if denominator <= f32::EPSILON {
+        return;
+    }
+    let delta_lambda = (-constraint - alpha * *lambda) / denominator;
+    *lambda += delta_lambda;
+
+    for &i in &cluster.members {
+        let offset = positions[i] - centroid;
+        let distance = offset.length();
+        if distance <= f32::EPSILON {
+            continue;
+        }
+        positions[i] += (offset / distance) * (delta_lambda * inverse_masses[i] / count);
+    }
+}
+
+pub fn centroid(positions: &[Vector3]) -> Vector3 {
+    #[allow(clippy::cast_precision_loss)]
+    let count = positions.len() as f32;
+    positions
+        .iter()
+        .fold(Vector3::ZERO, |sum, &position| sum + position)
+        / count
+}
+
+fn mean_radius(positions: &[Vector3]) -> f32 {
+    #[allow(clippy::cast_precision_loss)]
+    let count = positions.len() as f32;
+    let centroid = centroid(positions);
+    positions
+        .iter()
+        .map(|&position| position.distance_to(centroid))
+        .sum::<f32>()
+        / count
+}
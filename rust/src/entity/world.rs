@@ -0,0 +1,73 @@
+use super::{Entity, PARTICLE_DISTANCE};
+use anyhow::{Context, Result};
+use godot::{
+    engine::{PhysicsShapeQueryParameters3D, Resource, SphereShape3D},
+    prelude::*,
+};
+
+// How close static geometry has to be to a particle before it becomes a contact
+const CONTACT_RADIUS: f32 = PARTICLE_DISTANCE;
+
+// Half-space a particle must stay above, from the nearest surface of the physics world
+#[derive(Clone, Copy)]
+pub struct ContactPlane {
+    point: Vector3,
+    normal: Vector3,
+}
+
+impl ContactPlane {
+    // Push the position back out to the surface if it has sunk below it
+    pub fn project(&self, position: Vector3) -> Vector3 {
+        let depth = (position - self.point).dot(self.normal);
+        if depth < 0.0 {
+            position - self.normal * depth
+        } else {
+            position
+        }
+    }
+}
+
+impl Entity {
+    // Query the physics world around each surface particle, interior ones can't reach anything
+    pub fn gather_world_contacts(&self) -> Result<Vec<Option<ContactPlane>>> {
+        let mut space_state = self
+            .base
+            .get_world_3d()
+            .context("Entity is not in a world")?
+            .get_direct_space_state()
+            .context("Failed to get the physics space state")?;
+
+        let mut sphere = SphereShape3D::new();
+        sphere.set_radius(CONTACT_RADIUS);
+        let mut query = PhysicsShapeQueryParameters3D::new();
+        query.set_shape(sphere.upcast::<Resource>());
+        let mut exclude = Array::new();
+        exclude.push(self.base.get_rid());
+        query.set_exclude(exclude);
+
+        let mut contacts = Vec::with_capacity(self.particles.len());
+        for particle in &self.particles {
+            if particle.interior {
+                contacts.push(None);
+                continue;
+            }
+            query.set_transform(Transform3D::new(Basis::IDENTITY, particle.position));
+            let rest_info = space_state.get_rest_info(query.clone());
+            let contact = match (rest_info.get("point"), rest_info.get("normal")) {
+                (Some(point), Some(normal)) => Some(ContactPlane {
+                    point: point
+                        .try_to::<Vector3>()
+                        .ok()
+                        .context("Contact point is not a Vector3")?,
+                    normal: normal
+                        .try_to::<Vector3>()
+                        .ok()
+                        .context("Contact normal is not a Vector3")?,
+                }),
+                _ => None,
+            };
+            contacts.push(contact);
+        }
+        Ok(contacts)
+    }
+}
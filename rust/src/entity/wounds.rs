@@ -0,0 +1,138 @@
+use super::{
+    materials::PropertiesTable, spatial::SpatialHash, Entity, ParticleMaterial, PARTICLE_DISTANCE,
+};
+use godot::{
+    engine::{
+        base_material_3d::Flags, particle_process_material::EmissionShape,
+        particle_process_material::Parameter, GpuParticles3D, Material, Mesh,
+        ParticleProcessMaterial, SphereMesh, StandardMaterial3D,
+    },
+    prelude::*,
+};
+
+// How far from a wound the surrounding particles get darkened
+const BRUISE_RADIUS: f32 = PARTICLE_DISTANCE * 2.0;
+// Seconds each piece of debris lasts
+const DEBRIS_LIFETIME: f64 = 0.6;
+const DEBRIS_SIZE: f32 = 0.02;
+// Furthest a site can be from the first site of its burst, so separate wounds spray separately
+const DEBRIS_CLUSTER_RADIUS: f32 = PARTICLE_DISTANCE * 3.0;
+
+impl Entity {
+    // Show where connections were severed, given the particles either side of each
+    pub fn wound(&mut self, severed: &[(usize, usize)], properties: &PropertiesTable) {
+        if severed.is_empty() {
+            return;
+        }
+
+        // Gather the sites by material, each side of a cut sprays its own debris
+        let mut sites: Vec<(ParticleMaterial, Vec<Vector3>)> = Vec::new();
+        for &(a, b) in severed {
+            for index in [a, b] {
+                let particle = &self.particles[index];
+                match sites
+                    .iter_mut()
+                    .find(|(material, _)| *material == particle.material)
+                {
+                    Some((_, positions)) => positions.push(particle.position),
+                    None => sites.push((particle.material.clone(), vec![particle.position])),
+                }
+            }
+        }
+
+        // Darken the particles around each site
+        let grid = SpatialHash::from_positions(
+            BRUISE_RADIUS,
+            self.particles.iter().map(|particle| particle.position),
+        );
+        for (_, positions) in &sites {
+            for &site in positions {
+                for index in grid.neighbors(site) {
+                    let particle = &mut self.particles[index];
+                    if particle.position.distance_to(site) <= BRUISE_RADIUS {
+                        let darkening = properties.get(&particle.material).wound_darkening;
+                        particle.bruise = (particle.bruise + darkening).min(1.0);
+                    }
+                }
+            }
+        }
+
+        for (material, positions) in sites {
+            let material = properties.get(&material);
+            if material.wound_debris > 0 {
+                for cluster in cluster_sites(&positions) {
+                    self.spawn_debris(&cluster, material.wound_color, material.wound_debris);
+                }
+            }
+        }
+    }
+
+    // One shot burst covering a cluster of sites, freeing itself once finished
+    fn spawn_debris(&mut self, positions: &[Vector3], color: Color, amount: u32) {
+        #[allow(clippy::cast_precision_loss)]
+        let center = positions
+            .iter()
+            .fold(Vector3::ZERO, |sum, &position| sum + position)
+            / positions.len() as f32;
+        let radius = positions
+            .iter()
+            .map(|position| position.distance_to(center))
+            .fold(DEBRIS_SIZE, f32::max);
+
+        let mut process_material = ParticleProcessMaterial::new();
+        process_material.set_emission_shape(EmissionShape::EMISSION_SHAPE_SPHERE);
+        process_material.set_emission_sphere_radius(radius);
+        process_material.set_direction(Vector3::UP);
+        process_material.set_spread(180.0);
+        process_material.set_param_min(Parameter::PARAM_INITIAL_LINEAR_VELOCITY, 0.5);
+        process_material.set_param_max(Parameter::PARAM_INITIAL_LINEAR_VELOCITY, 2.0);
+        process_material.set_gravity(Vector3::new(0.0, -10.0, 0.0));
+        process_material.set_color(color);
+
+        let mut draw_material = StandardMaterial3D::new();
+        draw_material.set_flag(Flags::FLAG_ALBEDO_FROM_VERTEX_COLOR, true);
+        let mut mesh = SphereMesh::new();
+        mesh.set_radius(DEBRIS_SIZE);
+        mesh.set_height(DEBRIS_SIZE * 2.0);
+        mesh.set_radial_segments(4);
+        mesh.set_rings(2);
+        mesh.set_material(draw_material.upcast::<Material>());
+
+        let mut debris = GpuParticles3D::new_alloc();
+        #[allow(clippy::cast_possible_wrap)]
+        debris.set_amount(amount as i32);
+        debris.set_lifetime(DEBRIS_LIFETIME);
+        debris.set_one_shot(true);
+        debris.set_explosiveness_ratio(1.0);
+        debris.set_process_material(process_material.upcast::<Material>());
+        debris.set_draw_pass_mesh(0, mesh.upcast::<Mesh>());
+        debris.set_position(self.base.get_global_transform().affine_inverse() * center);
+        debris.connect(
+            "finished".into(),
+            Callable::from_object_method(debris.clone(), "queue_free"),
+        );
+        self.base.add_child(debris.clone().upcast::<Node>());
+        debris.set_emitting(true);
+    }
+}
+
+// Group the sites around the first unclaimed one in turn, each group small enough for one burst
+fn cluster_sites(positions: &[Vector3]) -> Vec<Vec<Vector3>> {
+    let grid = SpatialHash::from_positions(DEBRIS_CLUSTER_RADIUS, positions.iter().copied());
+    let mut claimed = vec![false; positions.len()];
+    let mut clusters = Vec::new();
+    for (first, &center) in positions.iter().enumerate() {
+        if claimed[first] {
+            continue;
+        }
+        let mut cluster = Vec::new();
+        for index in grid.neighbors(center) {
+            if !claimed[index] && positions[index].distance_to(center) <= DEBRIS_CLUSTER_RADIUS {
+                claimed[index] = true;
+                cluster.push(positions[index]);
+            }
+        }
+        clusters.push(cluster);
+    }
+    clusters
+}